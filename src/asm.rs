@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use crate::disasm::START;
+use crate::opcode::{Opcode, OpcodeTypes};
+
+/// Includes nested deeper than this are assumed to be a cycle
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+const RESERVED: [&str; 10] = ["I", "[I]", "DT", "ST", "K", "F", "B", "HF", "R", "PITCH"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError{
+    pub file : String,
+    pub line : usize,
+    pub message : String
+}
+
+impl fmt::Display for AsmError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+enum Body{
+    Instruction(String, Vec<String>),
+    Bytes(Vec<String>),
+    Words(Vec<String>)
+}
+
+struct Statement{
+    file : String,
+    line : usize,
+    addr : u16,
+    body : Body
+}
+
+enum Operand<'a>{
+    Reg(u16),
+    /// One of the RESERVED names such as DT or [I]
+    Named(&'static str),
+    Long(&'a str),
+    Value(&'a str)
+}
+
+/// Assembles the mnemonics listed by the disassembler into a ROM loaded at 0x200, see [`assemble_file`] for the syntax.
+/// Includes are looked up relative to the working directory.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError>{
+    let mut assembler = Assembler::new();
+    assembler.parse(source, "<source>", Path::new("."), 0)?;
+    assembler.encode()
+}
+
+/// Assembles a source file. Each line holds an optional `label:`, then an instruction or one of
+/// `db 1, 0x2, 0b11`, `dw 0x1234`, `include "file.s"` or `NAME = value`. Text after `;` is a comment and a leading
+/// address such as `0x200:` is ignored, so disasm listings assemble unchanged.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError>{
+    let mut assembler = Assembler::new();
+    assembler.include(path, "<command line>", 0, 0)?;
+    assembler.encode()
+}
+
+struct Assembler{
+    statements : Vec<Statement>,
+    symbols : HashMap<String, i64>,
+    addr : u32
+}
+
+impl Assembler{
+    fn new() -> Assembler{
+        Assembler {statements : Vec::new(), symbols : HashMap::new(), addr : START as u32}
+    }
+
+    fn include(&mut self, path: &Path, file: &str, line: usize, depth: usize) -> Result<(), AsmError>{
+        let err = |message: String| AsmError {file : file.to_string(), line, message};
+        if depth > MAX_INCLUDE_DEPTH{
+            return Err(err(format!("Includes nested more than {} deep", MAX_INCLUDE_DEPTH)));
+        }
+
+        let source = fs::read_to_string(path).map_err(|io| err(format!("Cannot read {}: {}", path.display(), io)))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        self.parse(&source, &path.display().to_string(), dir, depth)
+    }
+
+    /// First pass, which records every statement at its address and defines labels and constants
+    fn parse(&mut self, source: &str, file: &str, dir: &Path, depth: usize) -> Result<(), AsmError>{
+        for (index, text) in source.lines().enumerate(){
+            let line = index + 1;
+            let err = |message: String| AsmError {file : file.to_string(), line, message};
+
+            let mut rest = strip_comment(text).trim();
+            while let Some((name, after)) = rest.split_once(':').filter(|(name, _)| !name.contains(char::is_whitespace) && !name.is_empty()){
+                if parse_number(name).is_none(){
+                    self.define(name, self.addr as i64).map_err(err)?;
+                }
+                rest = after.trim();
+            }
+            if rest.is_empty(){
+                continue;
+            }
+
+            if let Some((name, value)) = rest.split_once('=').filter(|(name, _)| is_identifier(name.trim())){
+                let value = self.value(value.trim()).map_err(err)?;
+                self.define(name.trim(), value).map_err(err)?;
+                continue;
+            }
+
+            let (mnemonic, operands) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let operands: Vec<String> = operands.split(',').map(|operand| operand.trim().to_string()).filter(|operand| !operand.is_empty()).collect();
+            let mnemonic = mnemonic.to_ascii_uppercase();
+
+            let (body, size) = match mnemonic.as_str(){
+                "INCLUDE" => {
+                    let name = operands.first().and_then(|name| name.strip_prefix('"')).and_then(|name| name.strip_suffix('"'))
+                        .ok_or_else(|| err("include takes a quoted file name".to_string()))?;
+                    self.include(&dir.join(name), file, line, depth + 1)?;
+                    continue;
+                },
+                "DB" => (Body::Bytes(operands.clone()), operands.len()),
+                "DW" => (Body::Words(operands.clone()), 2 * operands.len()),
+                _ => {
+                    let long = operands.get(1).is_some_and(|operand| matches!(parse_operand(operand), Operand::Long(_)));
+                    (Body::Instruction(mnemonic, operands), if long {4} else {2})
+                }
+            };
+
+            if self.addr as usize + size > 0x10000{
+                return Err(err("Program does not fit in 64 KiB".to_string()));
+            }
+            self.statements.push(Statement {file : file.to_string(), line, addr : self.addr as u16, body});
+            self.addr += size as u32;
+        }
+
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, value: i64) -> Result<(), String>{
+        if !is_identifier(name) || RESERVED.contains(&name.to_ascii_uppercase().as_str()) || parse_register(name).is_some(){
+            return Err(format!("{} cannot be used as a name", name));
+        }
+        if self.symbols.insert(name.to_string(), value).is_some(){
+            return Err(format!("{} is defined twice", name));
+        }
+
+        Ok(())
+    }
+
+    fn value(&self, text: &str) -> Result<i64, String>{
+        parse_number(text).or_else(|| self.symbols.get(text).copied()).ok_or_else(|| format!("Unknown value {}", text))
+    }
+
+    /// Checks `text` fits in `bits` bits without a sign, as addresses, registers and nibbles must
+    fn field(&self, text: &str, bits: u32) -> Result<u16, String>{
+        self.checked_field(text, bits, 0)
+    }
+
+    /// Like field, but also allows negative numbers, which are stored as two's complement. Only data bytes and words
+    /// are signed.
+    fn signed_field(&self, text: &str, bits: u32) -> Result<u16, String>{
+        self.checked_field(text, bits, -(1 << (bits - 1)))
+    }
+
+    fn checked_field(&self, text: &str, bits: u32, min: i64) -> Result<u16, String>{
+        let value = self.value(text)?;
+        if value < min || value >= 1 << bits{
+            return Err(format!("{} does not fit in {} bits", text, bits));
+        }
+
+        Ok((value & ((1 << bits) - 1)) as u16)
+    }
+
+    /// Second pass, which resolves symbols and encodes each statement
+    fn encode(&self) -> Result<Vec<u8>, AsmError>{
+        let mut rom = Vec::new();
+
+        for statement in self.statements.iter(){
+            let err = |message: String| AsmError {file : statement.file.clone(), line : statement.line, message};
+            debug_assert_eq!(START as usize + rom.len(), statement.addr as usize);
+
+            match &statement.body{
+                Body::Bytes(values) => for value in values{
+                    rom.push(self.signed_field(value, 8).map_err(err)? as u8);
+                },
+                Body::Words(values) => for value in values{
+                    rom.extend(self.signed_field(value, 16).map_err(err)?.to_be_bytes());
+                },
+                Body::Instruction(mnemonic, operands) => {
+                    let (kind, code, long) = self.instruction(mnemonic, operands).map_err(err)?;
+                    if Opcode::find_kind(code) != Ok(kind){
+                        return Err(err(format!("{} encodes to {:04X} which does not decode as {:?}", mnemonic, code, kind)));
+                    }
+
+                    rom.extend(code.to_be_bytes());
+                    if let Some(long) = long{
+                        rom.extend(long.to_be_bytes());
+                    }
+                }
+            }
+        }
+
+        Ok(rom)
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[String]) -> Result<(OpcodeTypes, u16, Option<u16>), String>{
+        use Operand::*;
+
+        let parsed: Vec<Operand> = operands.iter().map(|operand| parse_operand(operand)).collect();
+        let addr = |text| self.field(text, 12);
+        let byte = |text| self.signed_field(text, 8);
+        let nibble = |text| self.field(text, 4);
+        let xy = |x: u16, y: u16| (x << 8) | (y << 4);
+
+        let encoded = match (mnemonic, parsed.as_slice()){
+            ("CLS", []) => (OpcodeTypes::CLS, 0x00E0),
+            ("RET", []) => (OpcodeTypes::RET, 0x00EE),
+            ("SCR", []) => (OpcodeTypes::SCR, 0x00FB),
+            ("SCL", []) => (OpcodeTypes::SCL, 0x00FC),
+            ("EXIT", []) => (OpcodeTypes::EXIT, 0x00FD),
+            ("LOW", []) => (OpcodeTypes::LOW, 0x00FE),
+            ("HIGH", []) => (OpcodeTypes::HIGH, 0x00FF),
+            ("AUDIO", []) => (OpcodeTypes::AUDIO, 0xF002),
+            ("SCD", [Value(n)]) => (OpcodeTypes::SCDNibble, 0x00C0 | nibble(n)?),
+            ("SCU", [Value(n)]) => (OpcodeTypes::SCUNibble, 0x00D0 | nibble(n)?),
+            ("PLANE", [Value(n)]) => (OpcodeTypes::PLANENibble, 0xF001 | (self.field(n, 2)? << 8)),
+            ("JP", [Value(nnn)]) => (OpcodeTypes::JPAddr, 0x1000 | addr(nnn)?),
+            ("JP", [Reg(0), Value(nnn)]) => (OpcodeTypes::JPV0Addr, 0xB000 | addr(nnn)?),
+            ("CALL", [Value(nnn)]) => (OpcodeTypes::CALLAddr, 0x2000 | addr(nnn)?),
+            ("SE", [Reg(x), Value(kk)]) => (OpcodeTypes::SEVxByte, 0x3000 | (x << 8) | byte(kk)?),
+            ("SNE", [Reg(x), Value(kk)]) => (OpcodeTypes::SNEVxByte, 0x4000 | (x << 8) | byte(kk)?),
+            ("SE", [Reg(x), Reg(y)]) => (OpcodeTypes::SEVxVy, 0x5000 | xy(*x, *y)),
+            ("SNE", [Reg(x), Reg(y)]) => (OpcodeTypes::SNEVxVy, 0x9000 | xy(*x, *y)),
+            ("LD", [Reg(x), Value(kk)]) => (OpcodeTypes::LDVxbyte, 0x6000 | (x << 8) | byte(kk)?),
+            ("ADD", [Reg(x), Value(kk)]) => (OpcodeTypes::ADDVxbyte, 0x7000 | (x << 8) | byte(kk)?),
+            ("LD", [Reg(x), Reg(y)]) => (OpcodeTypes::LDVxVy, 0x8000 | xy(*x, *y)),
+            ("OR", [Reg(x), Reg(y)]) => (OpcodeTypes::ORVxVy, 0x8001 | xy(*x, *y)),
+            ("AND", [Reg(x), Reg(y)]) => (OpcodeTypes::ANDVxVy, 0x8002 | xy(*x, *y)),
+            ("XOR", [Reg(x), Reg(y)]) => (OpcodeTypes::XORVxVy, 0x8003 | xy(*x, *y)),
+            ("ADD", [Reg(x), Reg(y)]) => (OpcodeTypes::ADDVxVy, 0x8004 | xy(*x, *y)),
+            ("SUB", [Reg(x), Reg(y)]) => (OpcodeTypes::SUBVxVy, 0x8005 | xy(*x, *y)),
+            ("SHR", [Reg(x)]) => (OpcodeTypes::SHRVxVy, 0x8006 | xy(*x, *x)),
+            ("SHR", [Reg(x), Reg(y)]) => (OpcodeTypes::SHRVxVy, 0x8006 | xy(*x, *y)),
+            ("SUBN", [Reg(x), Reg(y)]) => (OpcodeTypes::SUBNVxVy, 0x8007 | xy(*x, *y)),
+            ("SHL", [Reg(x)]) => (OpcodeTypes::SHLVxVy, 0x800E | xy(*x, *x)),
+            ("SHL", [Reg(x), Reg(y)]) => (OpcodeTypes::SHLVxVy, 0x800E | xy(*x, *y)),
+            ("LD", [Named("I"), Value(nnn)]) => (OpcodeTypes::LDIAddr, 0xA000 | addr(nnn)?),
+            ("LD", [Named("I"), Long(nnnn)]) => return Ok((OpcodeTypes::LDILong, 0xF000, Some(self.field(nnnn, 16)?))),
+            ("RND", [Reg(x), Value(kk)]) => (OpcodeTypes::RNDVxbyte, 0xC000 | (x << 8) | byte(kk)?),
+            ("DRW", [Reg(x), Reg(y), Value(n)]) => (OpcodeTypes::DRWVxVyNibble, 0xD000 | xy(*x, *y) | nibble(n)?),
+            ("SKP", [Reg(x)]) => (OpcodeTypes::SKPVx, 0xE09E | (x << 8)),
+            ("SKNP", [Reg(x)]) => (OpcodeTypes::SKNPVx, 0xE0A1 | (x << 8)),
+            ("LD", [Reg(x), Named("DT")]) => (OpcodeTypes::LDVxDT, 0xF007 | (x << 8)),
+            ("LD", [Reg(x), Named("K")]) => (OpcodeTypes::LDVxK, 0xF00A | (x << 8)),
+            ("LD", [Named("DT"), Reg(x)]) => (OpcodeTypes::LDDTVx, 0xF015 | (x << 8)),
+            ("LD", [Named("ST"), Reg(x)]) => (OpcodeTypes::LDSTVx, 0xF018 | (x << 8)),
+            ("ADD", [Named("I"), Reg(x)]) => (OpcodeTypes::ADDIVx, 0xF01E | (x << 8)),
+            ("LD", [Named("F"), Reg(x)]) => (OpcodeTypes::LDFVx, 0xF029 | (x << 8)),
+            ("LD", [Named("HF"), Reg(x)]) => (OpcodeTypes::LDHFVx, 0xF030 | (x << 8)),
+            ("LD", [Named("B"), Reg(x)]) => (OpcodeTypes::LDBVx, 0xF033 | (x << 8)),
+            ("LD", [Named("PITCH"), Reg(x)]) => (OpcodeTypes::LDPITCHVx, 0xF03A | (x << 8)),
+            ("LD", [Named("[I]"), Reg(x)]) => (OpcodeTypes::LDIVx, 0xF055 | (x << 8)),
+            ("LD", [Reg(x), Named("[I]")]) => (OpcodeTypes::LDVxI, 0xF065 | (x << 8)),
+            ("LD", [Named("R"), Reg(x)]) => (OpcodeTypes::LDRVx, 0xF075 | (x << 8)),
+            ("LD", [Reg(x), Named("R")]) => (OpcodeTypes::LDVxR, 0xF085 | (x << 8)),
+            ("LD", [Named("[I]"), Reg(x), Reg(y)]) => (OpcodeTypes::LDIVxVy, 0x5002 | xy(*x, *y)),
+            ("LD", [Reg(x), Reg(y), Named("[I]")]) => (OpcodeTypes::LDVxVyI, 0x5003 | xy(*x, *y)),
+            _ => return Err(format!("Unknown instruction {} {}", mnemonic, operands.join(", ")))
+        };
+
+        Ok((encoded.0, encoded.1, None))
+    }
+}
+
+fn parse_operand(text: &str) -> Operand<'_>{
+    let upper = text.to_ascii_uppercase();
+    if let Some(reg) = parse_register(text){
+        Operand::Reg(reg)
+    }
+    else if let Some(name) = RESERVED.iter().find(|&&name| name == upper){
+        Operand::Named(name)
+    }
+    else if upper.starts_with("LONG ") {
+        Operand::Long(text[5..].trim())
+    }
+    else{
+        Operand::Value(text)
+    }
+}
+
+fn parse_register(text: &str) -> Option<u16>{
+    let digit = text.strip_prefix(['V', 'v'])?;
+    if digit.len() != 1{
+        return None;
+    }
+    u16::from_str_radix(digit, 16).ok()
+}
+
+fn parse_number(text: &str) -> Option<i64>{
+    let (negative, digits) = match text.strip_prefix('-'){
+        Some(digits) => (true, digits),
+        None => (false, text)
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")){
+        i64::from_str_radix(hex, 16).ok()?
+    }
+    else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")){
+        i64::from_str_radix(binary, 2).ok()?
+    }
+    else{
+        digits.parse::<i64>().ok()?
+    };
+
+    Some(if negative {-value} else {value})
+}
+
+fn is_identifier(text: &str) -> bool{
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Cuts the line at the first `;` outside a quoted file name
+fn strip_comment(line: &str) -> &str{
+    let mut quoted = false;
+    for (index, c) in line.char_indices(){
+        match c{
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::assemble;
+    use crate::disasm::disassemble;
+    use crate::opcode::Platform;
+
+    #[test]
+    fn disassembly_round_trips(){
+        let roms: [&[u8]; 3] = [include_bytes!("../TestRoms/test_opcode.ch8"), include_bytes!("../TestRoms/c8_test.c8"), include_bytes!("../TestRoms/tetris.rom")];
+
+        for rom in roms{
+            for platform in [Platform::Chip8, Platform::XoChip]{
+                let listing: String = disassemble(rom, platform).unwrap().iter().map(|line| format!("{}\n", line)).collect();
+                assert_eq!(assemble(&listing).unwrap(), rom);
+            }
+        }
+    }
+
+    #[test]
+    fn labels_constants_and_data(){
+        let source = "
+            SPEED = 3          ; frames per move
+            start:
+                LD V0, SPEED
+                LD I, LONG sprite
+                CALL draw
+                JP start
+            draw: DRW V0, V1, 2
+                RET
+            sprite:
+                db 0b11000011, 0xFF
+                dw -1
+        ";
+
+        assert_eq!(assemble(source).unwrap(), [
+            0x60, 0x03, 0xF0, 0x00, 0x02, 0x0E, 0x22, 0x0A, 0x12, 0x00, 0xD0, 0x12, 0x00, 0xEE, 0xC3, 0xFF, 0xFF, 0xFF
+        ]);
+    }
+
+    #[test]
+    fn errors_name_the_line(){
+        let err = assemble("CLS\nLD V0, 0x100").unwrap_err();
+        assert_eq!((err.line, err.message.as_str()), (2, "0x100 does not fit in 8 bits"));
+
+        assert_eq!(assemble("PLANE 4").unwrap_err().message, "4 does not fit in 2 bits");
+        assert_eq!(assemble("JP nowhere").unwrap_err().message, "Unknown value nowhere");
+        assert!(assemble("LD DT, 5").is_err());
+        assert_eq!(assemble("JP -1").unwrap_err().message, "-1 does not fit in 12 bits");
+        assert_eq!(assemble("CALL -2048").unwrap_err().message, "-2048 does not fit in 12 bits");
+        assert_eq!(assemble("LD V0, -1\ndb -128\ndw -1").unwrap(), [0x60, 0xFF, 0x80, 0xFF, 0xFF]);
+    }
+}
@@ -0,0 +1,264 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use crate::cpu::Cpu;
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Samples in one 60Hz frame, which divides evenly so frames never drift from emulated time
+pub const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+
+const VOLUME: f32 = 0.25;
+
+/// Somewhere for mono samples between -1 and 1 at SAMPLE_RATE to go
+pub trait AudioSink{
+    fn write(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    /// Called once no more samples are coming
+    fn finish(&mut self) -> io::Result<()>{
+        Ok(())
+    }
+}
+
+/// Bits in the XO-CHIP audio pattern buffer
+const PATTERN_BITS: f64 = 128.0;
+
+/// Turns the sound timer into a square wave, or into the XO-CHIP audio pattern once one is loaded, one frame of
+/// samples at a time
+pub struct Beeper{
+    pub frequency : f32,
+    /// Position in the current wave period, from 0 to 1
+    phase : f32,
+    /// Position in the audio pattern in bits, from 0 to 128
+    pattern_phase : f64
+}
+
+/// Pattern bits played per second, 4000 at the default pitch of 64 and doubling every 48 steps
+pub fn pattern_rate(pitch: u8) -> f64{
+    4000.0 * 2_f64.powf((pitch as f64 - 64.0) / 48.0)
+}
+
+impl Beeper{
+    pub fn new() -> Beeper{
+        Beeper {frequency : 440.0, phase : 0.0, pattern_phase : 0.0}
+    }
+
+    /// Samples for the frame that ended at the last timer tick
+    pub fn frame(&mut self, processor: &Cpu) -> Vec<f32>{
+        if !processor.sounding{
+            return vec![0.0; SAMPLES_PER_FRAME];
+        }
+
+        let memory = &processor.memory;
+        if memory.pattern_loaded{
+            let step = pattern_rate(memory.pitch) / SAMPLE_RATE as f64;
+            return (0..SAMPLES_PER_FRAME).map(|_| {
+                let bit = self.pattern_phase as usize;
+                let sample = if memory.pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {VOLUME} else {-VOLUME};
+                self.pattern_phase = (self.pattern_phase + step) % PATTERN_BITS;
+                sample
+            }).collect();
+        }
+
+        (0..SAMPLES_PER_FRAME).map(|_| {
+            let sample = if self.phase < 0.5 {VOLUME} else {-VOLUME};
+            self.phase = (self.phase + self.frequency / SAMPLE_RATE as f32).fract();
+            sample
+        }).collect()
+    }
+}
+
+impl Default for Beeper{
+    fn default() -> Self{
+        Beeper::new()
+    }
+}
+
+/// Writes 16-bit mono PCM, filling in the header sizes on finish
+pub struct WavSink<W: Write + Seek>{
+    writer : W,
+    samples : u32
+}
+
+impl WavSink<BufWriter<File>>{
+    pub fn create(path: &Path) -> io::Result<Self>{
+        WavSink::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek> WavSink<W>{
+    pub fn new(mut writer: W) -> io::Result<Self>{
+        writer.write_all(&wav_header(0))?;
+        Ok(WavSink {writer, samples : 0})
+    }
+
+    pub fn into_inner(self) -> W{
+        self.writer
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W>{
+    fn write(&mut self, samples: &[f32]) -> io::Result<()>{
+        for sample in samples{
+            self.writer.write_all(&((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())?;
+        }
+        self.samples += samples.len() as u32;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()>{
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&wav_header(self.samples))?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+fn wav_header(samples: u32) -> [u8; 44]{
+    let data_len = samples * 2;
+    let mut header = [0; 44];
+
+    header[0..4].copy_from_slice(b"RIFF");
+    header[4..8].copy_from_slice(&(36 + data_len).to_le_bytes());
+    header[8..16].copy_from_slice(b"WAVEfmt ");
+    header[16..20].copy_from_slice(&16_u32.to_le_bytes());
+    // PCM, one channel
+    header[20..22].copy_from_slice(&1_u16.to_le_bytes());
+    header[22..24].copy_from_slice(&1_u16.to_le_bytes());
+    header[24..28].copy_from_slice(&SAMPLE_RATE.to_le_bytes());
+    header[28..32].copy_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    header[32..34].copy_from_slice(&2_u16.to_le_bytes());
+    header[34..36].copy_from_slice(&16_u16.to_le_bytes());
+    header[36..40].copy_from_slice(b"data");
+    header[40..44].copy_from_slice(&data_len.to_le_bytes());
+
+    header
+}
+
+#[cfg(feature = "audio")]
+pub use device::DeviceSink;
+
+/// Live output through the system's default audio device
+#[cfg(feature = "audio")]
+mod device{
+    use std::collections::VecDeque;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use cpal::{FromSample, SizedSample};
+    use super::{AudioSink, SAMPLE_RATE};
+
+    /// Samples beyond this are dropped to keep latency down when the device falls behind
+    const MAX_QUEUED: usize = SAMPLE_RATE as usize / 10;
+
+    pub struct DeviceSink{
+        queue : Arc<Mutex<VecDeque<f32>>>,
+        _stream : cpal::Stream
+    }
+
+    impl DeviceSink{
+        pub fn open() -> Result<DeviceSink, String>{
+            let device = cpal::default_host().default_output_device().ok_or("No audio output device")?;
+            let supported = device.default_output_config().map_err(|err| err.to_string())?;
+            let config = supported.config();
+            let queue = Arc::new(Mutex::new(VecDeque::new()));
+
+            let stream = match supported.sample_format(){
+                cpal::SampleFormat::F32 => build::<f32>(&device, &config, queue.clone()),
+                cpal::SampleFormat::I16 => build::<i16>(&device, &config, queue.clone()),
+                cpal::SampleFormat::U16 => build::<u16>(&device, &config, queue.clone()),
+                format => return Err(format!("Unsupported sample format {}", format))
+            }.map_err(|err| err.to_string())?;
+            stream.play().map_err(|err| err.to_string())?;
+
+            Ok(DeviceSink {queue, _stream : stream})
+        }
+    }
+
+    /// Plays the queue at the device's rate and channel count, repeating or skipping samples to convert from SAMPLE_RATE
+    fn build<T: SizedSample + FromSample<f32>>(device: &cpal::Device, config: &cpal::StreamConfig, queue: Arc<Mutex<VecDeque<f32>>>)
+        -> Result<cpal::Stream, cpal::BuildStreamError>{
+        let channels = config.channels as usize;
+        let step = SAMPLE_RATE as f64 / config.sample_rate.0 as f64;
+        let mut position = 0.0;
+        let mut current = 0.0;
+
+        device.build_output_stream(config, move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let mut queue = queue.lock().unwrap();
+            for frame in data.chunks_mut(channels){
+                position += step;
+                while position >= 1.0{
+                    current = queue.pop_front().unwrap_or(0.0);
+                    position -= 1.0;
+                }
+                frame.iter_mut().for_each(|out| *out = T::from_sample(current));
+            }
+        }, |err| eprintln!("Audio error: {}", err), None)
+    }
+
+    impl AudioSink for DeviceSink{
+        fn write(&mut self, samples: &[f32]) -> io::Result<()>{
+            let mut queue = self.queue.lock().unwrap();
+            queue.extend(samples);
+            let excess = queue.len().saturating_sub(MAX_QUEUED);
+            queue.drain(..excess);
+
+            Ok(())
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{pattern_rate, AudioSink, Beeper, WavSink, SAMPLES_PER_FRAME};
+    use crate::cpu::Cpu;
+    use crate::quirks::Quirks;
+    use std::io::Cursor;
+
+    #[test]
+    fn beeps_while_sound_timer_runs(){
+        let mut processor = Cpu::new(&[]).unwrap();
+        let mut beeper = Beeper::new();
+        let mut sink = WavSink::new(Cursor::new(Vec::new())).unwrap();
+
+        processor.memory.sound = 2;
+        for _ in 0..3{
+            processor.tick_timers();
+            sink.write(&beeper.frame(&processor)).unwrap();
+        }
+        sink.finish().unwrap();
+        let wav = sink.into_inner().into_inner();
+
+        let samples: Vec<i16> = wav[44..].chunks(2).map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]])).collect();
+        assert_eq!(samples.len(), 3 * SAMPLES_PER_FRAME);
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 2 * samples.len() as u32);
+        // 440Hz at 44100Hz flips roughly every 50 samples
+        assert!(samples[..50].iter().all(|&sample| sample > 0) && samples[51..100].iter().all(|&sample| sample < 0));
+        assert!(samples[2 * SAMPLES_PER_FRAME..].iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn plays_loaded_pattern(){
+        // LD I, 0x206 ; AUDIO ; JP 0x204 ; then a pattern of 8 high bits and 8 low bits, repeated
+        let mut rom = vec![0xA2, 0x06, 0xF0, 0x02, 0x12, 0x04];
+        rom.extend([0xFF, 0x00].repeat(8));
+        let mut processor = Cpu::with_quirks(&rom, Quirks::xo_chip()).unwrap();
+        let mut beeper = Beeper::new();
+        processor.run(2).unwrap();
+        assert!(processor.memory.pattern_loaded);
+
+        processor.memory.sound = 1;
+        processor.tick_timers();
+        let samples = beeper.frame(&processor);
+        assert_eq!(samples.len(), SAMPLES_PER_FRAME);
+        assert_eq!(pattern_rate(64), 4000.0);
+        assert_eq!(pattern_rate(112), 8000.0);
+
+        // 4000 bits a second at 44100Hz is about 11 samples a bit, so 88 high samples then 88 low
+        assert!(samples[..88].iter().all(|&sample| sample > 0.0));
+        assert!(samples[89..176].iter().all(|&sample| sample < 0.0));
+        assert!(samples[177..264].iter().all(|&sample| sample > 0.0));
+    }
+}
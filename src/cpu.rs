@@ -1,54 +1,80 @@
-use std::fs::File;
-use std::{path::Path, fs::read};
-use crate::memory::Memory;
-use crate::opcode::{Opcode, OpcodeTypes};
+use std::fs;
+use crate::memory::{Memory, MemoryAccess};
+use crate::display::Display;
+use crate::quirks::Quirks;
+use crate::error::EmulatorError;
+use crate::opcode::{Opcode, OpcodeTypes, Platform};
+use crate::rng::{RandomSource, SeededRng};
 extern crate rand;
-use crate::cpu::rand::Rng;
-extern crate minifb;
-use minifb::{Window, WindowOptions, Key};
-use std::collections::hash_map::HashMap;
-use std::io::Write;
 
 const FONT_SET: [[u8; 5]; 16] = [[0xF0,0x90,0x90,0x90,0xF0], [0x20,0x60,0x20,0x20,0x70,], [0xF0,0x10,0xF0,0x80,0xF0], [0xF0,0x10,0xF0,0x10,0xF0], [0x90,0x90,0xF0,0x10,0x10], 
 [0xF0,0x80,0xF0,0x10,0xF0], [0xF0,0x80,0xF0,0x90,0xF0], [0xF0,0x10,0x20,0x40,0x40], [0xF0,0x90,0xF0,0x90,0xF0], [0xF0,0x90,0xF0,0x10,0xF0], 
 [0xF0,0x90,0xF0,0x90,0x90], [0xE0,0x90,0xE0,0x90,0xE0], [0xF0,0x80,0x80,0x80,0xF0], [0xE0,0x90,0x90,0x90,0xE0], [0xF0,0x80,0xF0,0x80,0xF0], [0xF0,0x80,0xF0,0x80,0x80]];
 
+pub const BIG_FONT_START: usize = 0x50;
+const BIG_FONT_SET: [[u8; 10]; 16] = [[0x3C,0x7E,0xE7,0xC3,0xC3,0xC3,0xC3,0xE7,0x7E,0x3C], [0x18,0x38,0x58,0x18,0x18,0x18,0x18,0x18,0x18,0x3C],
+[0x3E,0x7F,0xC3,0x06,0x0C,0x18,0x30,0x60,0xFF,0xFF], [0x3C,0x7E,0xC3,0x03,0x0E,0x0E,0x03,0xC3,0x7E,0x3C], [0x06,0x0E,0x1E,0x36,0x66,0xC6,0xFF,0xFF,0x06,0x06],
+[0xFF,0xFF,0xC0,0xC0,0xFC,0xFE,0x03,0xC3,0x7E,0x3C], [0x3E,0x7C,0xC0,0xC0,0xFC,0xFE,0xC3,0xC3,0x7E,0x3C], [0xFF,0xFF,0x03,0x03,0x06,0x0C,0x18,0x18,0x18,0x18],
+[0x3C,0x7E,0xC3,0xC3,0x7E,0x7E,0xC3,0xC3,0x7E,0x3C], [0x3C,0x7E,0xC3,0xC3,0x7F,0x3F,0x03,0x03,0x3E,0x7C], [0x7E,0xFF,0xC3,0xC3,0xC3,0xFF,0xFF,0xC3,0xC3,0xC3],
+[0xFC,0xFE,0xC3,0xC3,0xFE,0xFE,0xC3,0xC3,0xFE,0xFC], [0x3C,0x7E,0xC3,0xC0,0xC0,0xC0,0xC0,0xC3,0x7E,0x3C], [0xFC,0xFE,0xC3,0xC3,0xC3,0xC3,0xC3,0xC3,0xFE,0xFC],
+[0xFF,0xFF,0xC0,0xC0,0xFF,0xFF,0xC0,0xC0,0xFF,0xFF], [0xFF,0xFF,0xC0,0xC0,0xFF,0xFF,0xC0,0xC0,0xC0,0xC0]];
+
 pub struct Cpu{
     pub opcode : Opcode,
     pub memory : Memory,
-    pub window : Window,
-    pub key_map : HashMap<Key, u8>,
-    pub curr_buffer : [[u32;64];32],
-    pub key : Vec<Key>
+    pub curr_buffer : Display,
+    /// Keys currently held down, indexed by CHIP-8 key value
+    pub keypad : [bool; 16],
+    /// Keys pressed since the last frame, used by LDVxK
+    pub key : Vec<u8>,
+    /// Set whenever curr_buffer changes so a frontend knows to redraw
+    pub draw_flag : bool,
+    /// Set by the SCHIP EXIT instruction, after which step does nothing
+    pub exited : bool,
+    pub quirks : Quirks,
+    /// Set after a draw when the display wait quirk is on, cleared by the next timer tick
+    pub vblank_wait : bool,
+    /// Memory accesses made by the last instruction
+    pub mem_accesses : Vec<MemoryAccess>,
+    /// Instructions executed since the machine was created
+    pub cycles : u64,
+    /// Whether the sound timer was running during the frame that ended at the last timer tick
+    pub sounding : bool,
+    /// Seed RNDVxbyte's generator was last started from
+    pub seed : u64,
+    pub rng : Box<dyn RandomSource>
 }
 
 impl Cpu{
-    pub fn initialize(file_path: &str) -> Cpu{
-        let path = Path::new(file_path);
-        let file_vec = read(path).unwrap();
+    pub fn initialize(file_path: &str) -> Result<Cpu, EmulatorError>{
+        let rom = fs::read(file_path).map_err(|err| EmulatorError::Io(format!("Cannot read {}: {}", file_path, err)))?;
 
-        let mut file = File::create("output.txt").expect("Failed to create file");
+        Cpu::new(&rom)
+    }
 
-        for item in file_vec.iter() {
-            writeln!(&mut file, "{:X?}", item).expect("Failed to write to file");
-        }
-            
+    /// A COSMAC VIP with the ROM loaded
+    pub fn new(rom: &[u8]) -> Result<Cpu, EmulatorError>{
+        Cpu::with_quirks(rom, Quirks::default())
+    }
 
-        let mut addr_mem: [u8; 4096] = [0; 4096];
+    /// Loads the ROM into as much memory as the quirks' platform has
+    pub fn with_quirks(rom: &[u8], quirks: Quirks) -> Result<Cpu, EmulatorError>{
+        let size = quirks.platform.memory_size();
+        let mut addr_mem = vec![0_u8; size];
 
-        if file_vec.len() > 4096{
-            panic!("Incorrect length of file")
+        if rom.len() > size - 512{
+            return Err(EmulatorError::RomTooLarge {size : rom.len(), max : size - 512});
         }
 
         for (start, bytes) in FONT_SET.iter().enumerate(){
-            addr_mem[(5 * start)] = bytes[0];
-            addr_mem[(5 * start) + 1] = bytes[1];
-            addr_mem[(5 * start) + 2] = bytes[2];
-            addr_mem[(5 * start) + 3] = bytes[3];
-            addr_mem[(5 * start) + 4] = bytes[4];
+            addr_mem[5 * start..5 * start + 5].copy_from_slice(bytes);
+        }
+
+        for (start, bytes) in BIG_FONT_SET.iter().enumerate(){
+            addr_mem[BIG_FONT_START + 10 * start..BIG_FONT_START + 10 * start + 10].copy_from_slice(bytes);
         }
 
-        for i in file_vec.iter().enumerate(){
+        for i in rom.iter().enumerate(){
             addr_mem[i.0 + 512] = *i.1
         }
 
@@ -60,73 +86,170 @@ impl Cpu{
             stack : [None; 16],
             sp : 0,
             delay : 0,
-            sound : 0
+            sound : 0,
+            rpl : [0; 16],
+            pattern : [0; 16],
+            pattern_loaded : false,
+            pitch : 64
         };
 
-        let key: Vec<Key> = [].to_vec();
-
         let opcode = Opcode{
             code : 0,
             kind : None
         };
 
-        let mut window = Window::new("CHIP-8", 640, 320, WindowOptions::default()).unwrap();
-        window.update_with_buffer(&[0; 2048], 64, 32).unwrap();
-
-        let curr_buffer = [[0;64];32];
-
-        let key_map = HashMap::from([
-            (Key::Key1,1 as u8),
-            (Key::Key2,2),
-            (Key::Key3,3),
-            (Key::Key4,12),
-            (Key::Q,4),
-            (Key::W,5),
-            (Key::E,6),
-            (Key::R,13),
-            (Key::A,7),
-            (Key::S,8),
-            (Key::D,9),
-            (Key::F,14),
-            (Key::Z,10),
-            (Key::X,0),
-            (Key::C,11),
-            (Key::V,15),
-        ]);
-
-        let mut file2 = File::create("output2.txt").expect("Failed to create file");
-        for i in addr_mem.iter().enumerate(){
-            writeln!(&mut file2, "{} : {:X?}", i.0, i.1).expect("Failed to write to file");
+        let seed = rand::random();
+        Ok(Cpu {opcode, memory, curr_buffer : Display::new(), keypad : [false; 16], key : Vec::new(), draw_flag : true, exited : false, quirks, vblank_wait : false,
+            mem_accesses : Vec::new(), cycles : 0, sounding : false, seed, rng : Box::new(SeededRng::new(seed))})
+    }
+
+    /// Restarts the random number generator so RNDVxbyte gives the same sequence every run
+    pub fn set_seed(&mut self, seed: u64){
+        self.seed = seed;
+        self.rng = Box::new(SeededRng::new(seed));
+    }
+
+    /// Returns the screen as a row-major pixel buffer, `curr_buffer.width` pixels wide
+    pub fn buffer(&self) -> Vec<u32>{
+        self.curr_buffer.to_rgb()
+    }
+
+    /// Sets which keys are held down; keys that went down since the last call are queued for LDVxK
+    pub fn set_keypad(&mut self, keypad: [bool; 16]){
+        self.key = (0..16_u8).filter(|&key| keypad[key as usize] && !self.keypad[key as usize]).collect();
+        self.keypad = keypad;
+    }
+
+    /// Decrements the delay and sound timers, meant to be called at 60Hz
+    pub fn tick_timers(&mut self){
+        self.vblank_wait = false;
+        self.sounding = self.memory.sound > 0;
+
+        if self.memory.sound > 0{
+            self.memory.sound -= 1;
+        }
+
+        if self.memory.delay > 0{
+            self.memory.delay -= 1;
+        }
+    }
+
+    /// Runs a single instruction and moves the pc on to the next one
+    pub fn step(&mut self) -> Result<(), EmulatorError>{
+        if self.exited || self.vblank_wait{
+            return Ok(());
         }
 
-        Cpu {opcode, memory, window, key_map, curr_buffer, key}
+        self.mem_accesses.clear();
+        self.fetch()?;
+        self.decode()?;
+        self.execute()?;
+        self.cycles += 1;
+
+        if self.opcode.kind != Some(OpcodeTypes::CALLAddr) && self.opcode.kind != Some(OpcodeTypes::SNEVxByte) &&
+        self.opcode.kind != Some(OpcodeTypes::RET) && self.opcode.kind != Some(OpcodeTypes::JPAddr) && self.opcode.kind != Some(OpcodeTypes::SEVxVy)
+        && self.opcode.kind != Some(OpcodeTypes::JPV0Addr) && self.opcode.kind != Some(OpcodeTypes::SKPVx) && self.opcode.kind != Some(OpcodeTypes::SKNPVx)
+        && self.opcode.kind != Some(OpcodeTypes::SEVxByte) && self.opcode.kind != Some(OpcodeTypes::SNEVxVy)
+        {
+            self.memory.pc = self.memory.pc.wrapping_add(2);
+        }
+
+        Ok(())
+    }
+
+    /// True when the last instruction jumped to itself, which is how most programs finish
+    pub fn halted(&self) -> bool{
+        let addr = (self.opcode.code & 0xFFF) as usize;
+        self.opcode.kind == Some(OpcodeTypes::JPAddr) && self.memory.addr_mem.get(addr..addr + 2) == Some(&self.opcode.code.to_be_bytes()[..])
+    }
+
+    pub fn run(&mut self, cycles: usize) -> Result<(), EmulatorError>{
+        for _ in 0..cycles{
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    fn out_of_bounds(&self, addr: usize) -> EmulatorError{
+        EmulatorError::OutOfBoundsMemory {pc : self.memory.pc, opcode : self.opcode.code, addr}
+    }
+
+    /// Reads from the instruction stream, which is not recorded in mem_accesses
+    fn peek_mem(&self, addr: usize) -> Result<u8, EmulatorError>{
+        self.memory.addr_mem.get(addr).copied().ok_or_else(|| self.out_of_bounds(addr))
+    }
+
+    fn read_mem(&mut self, addr: usize) -> Result<u8, EmulatorError>{
+        let value = self.peek_mem(addr)?;
+        self.mem_accesses.push(MemoryAccess {addr, write : false});
+
+        Ok(value)
+    }
+
+    fn write_mem(&mut self, addr: usize, value: u8) -> Result<(), EmulatorError>{
+        let err = self.out_of_bounds(addr);
+        *self.memory.addr_mem.get_mut(addr).ok_or(err)? = value;
+        self.mem_accesses.push(MemoryAccess {addr, write : true});
+
+        Ok(())
+    }
+
+    /// Moves the pc past the current instruction and the next one, which on XO-CHIP is 4 bytes long if it is F000 NNNN
+    fn skip(&mut self) -> Result<(), EmulatorError>{
+        let next = self.memory.pc as usize + 2;
+        let long = self.quirks.platform >= Platform::XoChip && self.peek_mem(next)? == 0xF0 && self.peek_mem(next + 1)? == 0x00;
+        let len = if long {4} else {2};
+
+        self.memory.pc = self.memory.pc.wrapping_add(2 + len);
+        Ok(())
     }
 
-    pub fn fetch(&mut self){
-        self.opcode.code = u16::from_be_bytes([self.memory.addr_mem[self.memory.pc as usize], self.memory.addr_mem[(self.memory.pc + 1) as usize]]);
-        println!("{:X?}", self.opcode.code)
+    pub fn fetch(&mut self) -> Result<(), EmulatorError>{
+        let pc = self.memory.pc as usize;
+        self.opcode.code = u16::from_be_bytes([self.peek_mem(pc)?, self.peek_mem(pc + 1)?]);
+
+        Ok(())
     }
 
-    pub fn decode(&mut self){
-        let kind = Opcode::find_kind(self.opcode.code).unwrap();
-        println!("{:?}\n", kind);
+    /// Instructions from a later platform than the quirks' are unknown, so data in a CHIP-8 ROM never runs as XO-CHIP
+    pub fn decode(&mut self) -> Result<(), EmulatorError>{
+        let kind = Opcode::find_kind(self.opcode.code).ok().filter(|kind| kind.platform() <= self.quirks.platform)
+            .ok_or(EmulatorError::UnknownOpcode {pc : self.memory.pc, opcode : self.opcode.code})?;
         self.opcode.kind = Some(kind);
+
+        Ok(())
     }
 
-    pub fn execute(&mut self){
-        match self.opcode.kind.as_ref().expect("incorrect opcode"){
+    pub fn execute(&mut self) -> Result<(), EmulatorError>{
+        let kind = match self.opcode.kind.as_ref(){
+            Some(kind) => kind,
+            None => return Err(EmulatorError::UnknownOpcode {pc : self.memory.pc, opcode : self.opcode.code})
+        };
+
+        match kind{
             OpcodeTypes::CLS => {
-                self.window.update_with_buffer(&[0; 64 * 32], 64, 32).unwrap()
+                self.curr_buffer.clear();
+                self.draw_flag = true;
             },
             OpcodeTypes::RET => {
+                let underflow = EmulatorError::StackUnderflow {pc : self.memory.pc, opcode : self.opcode.code};
+                if self.memory.sp == 0{
+                    return Err(underflow);
+                }
+
                 self.memory.sp -= 1;
-                self.memory.pc = self.memory.stack[self.memory.sp as usize].expect("None in stack") + 2;
+                self.memory.pc = self.memory.stack[self.memory.sp as usize].ok_or(underflow)?.wrapping_add(2);
                 self.memory.stack[self.memory.sp as usize] = None;
             },
             OpcodeTypes::JPAddr => {
                 self.memory.pc = self.opcode.code & 0x0FFF;
             },
             OpcodeTypes::CALLAddr => {
+                if self.memory.sp as usize >= self.memory.stack.len(){
+                    return Err(EmulatorError::StackOverflow {pc : self.memory.pc, opcode : self.opcode.code});
+                }
+
                 self.memory.stack[self.memory.sp as usize] = Some(self.memory.pc);
                 self.memory.sp += 1;
                 self.memory.pc = self.opcode.code & 0xFFF;
@@ -137,10 +260,10 @@ impl Cpu{
                 let comp_val = bytes[1];
 
                 if self.memory.reg[reg_no as usize] == comp_val{
-                    self.memory.pc += 4;
+                    self.skip()?;
                 }
                 else {
-                    self.memory.pc += 2;
+                    self.memory.pc = self.memory.pc.wrapping_add(2);
                 }
             },
             OpcodeTypes::SNEVxByte => {
@@ -149,10 +272,10 @@ impl Cpu{
                 let comp_val = bytes[1];
 
                 if self.memory.reg[reg_no as usize] != comp_val{
-                    self.memory.pc += 4;
+                    self.skip()?;
                 }
                 else{
-                    self.memory.pc += 2;
+                    self.memory.pc = self.memory.pc.wrapping_add(2);
                 }
             },
             OpcodeTypes::SEVxVy => {
@@ -161,10 +284,10 @@ impl Cpu{
                 let reg2 = bytes[1].rotate_left(4) & 0x0F;
 
                 if self.memory.reg[reg1 as usize] == self.memory.reg[reg2 as usize]{
-                    self.memory.pc += 4;
+                    self.skip()?;
                 }
                 else{
-                    self.memory.pc += 2;
+                    self.memory.pc = self.memory.pc.wrapping_add(2);
                 }
             },
             OpcodeTypes::LDVxbyte => {
@@ -191,7 +314,11 @@ impl Cpu{
                 let reg1 = bytes[0] & 0x0F;
                 let reg2 = bytes[1].rotate_left(4) & 0x0F;
 
-                self.memory.reg[reg1 as usize] = self.memory.reg[reg2 as usize] | self.memory.reg[reg1 as usize]
+                self.memory.reg[reg1 as usize] |= self.memory.reg[reg2 as usize];
+
+                if self.quirks.vf_reset{
+                    self.memory.reg[15] = 0;
+                }
             },
 
             OpcodeTypes::ANDVxVy => {
@@ -199,61 +326,71 @@ impl Cpu{
                 let reg1 = bytes[0] & 0x0F;
                 let reg2 = bytes[1].rotate_left(4) & 0x0F;
 
-                self.memory.reg[reg1 as usize] = self.memory.reg[reg2 as usize] & self.memory.reg[reg1 as usize]
+                self.memory.reg[reg1 as usize] &= self.memory.reg[reg2 as usize];
+
+                if self.quirks.vf_reset{
+                    self.memory.reg[15] = 0;
+                }
             },
             OpcodeTypes::XORVxVy => {
                 let bytes = self.opcode.code.to_be_bytes();
                 let reg1 = bytes[0] & 0x0F;
                 let reg2 = bytes[1].rotate_left(4) & 0x0F;
 
-                self.memory.reg[reg1 as usize] = self.memory.reg[reg2 as usize] ^ self.memory.reg[reg1 as usize]
+                self.memory.reg[reg1 as usize] ^= self.memory.reg[reg2 as usize];
+
+                if self.quirks.vf_reset{
+                    self.memory.reg[15] = 0;
+                }
             },
             OpcodeTypes::ADDVxVy => {
                 let bytes = self.opcode.code.to_be_bytes();
                 let reg1 = bytes[0] & 0x0F;
                 let reg2 = bytes[1].rotate_left(4) & 0x0F;
 
-                self.memory.reg[15] = (self.memory.reg[reg1 as usize] as u16 + self.memory.reg[reg2 as usize] as u16 > 255) as u8;
+                let (sum, carry) = self.memory.reg[reg1 as usize].overflowing_add(self.memory.reg[reg2 as usize]);
 
-                self.memory.reg[reg1 as usize] = ((self.memory.reg[reg1 as usize] as u16 + self.memory.reg[reg2 as usize] as u16) & 0x00FF).try_into().unwrap()
+                // VF is written last so it holds the flag when it is also Vx
+                self.memory.reg[reg1 as usize] = sum;
+                self.memory.reg[15] = carry as u8;
             },
             OpcodeTypes::SUBVxVy => {
                 let bytes = self.opcode.code.to_be_bytes();
                 let reg1 = bytes[0] & 0x0F;
                 let reg2 = bytes[1].rotate_left(4) & 0x0F;
 
-                self.memory.reg[15] = (self.memory.reg[reg1 as usize] < self.memory.reg[reg2 as usize]) as u8;
-                
-                let bor = u16::from_be_bytes([self.memory.reg[15], self.memory.reg[reg1 as usize]]);
-                self.memory.reg[reg1 as usize] = (bor - self.memory.reg[reg2 as usize] as u16) as u8
+                let (difference, borrow) = self.memory.reg[reg1 as usize].overflowing_sub(self.memory.reg[reg2 as usize]);
+
+                self.memory.reg[reg1 as usize] = difference;
+                self.memory.reg[15] = !borrow as u8;
             },
             OpcodeTypes::SHRVxVy => {
                 let bytes = self.opcode.code.to_be_bytes();
                 let reg = bytes[0] & 0x0F;
+                let src = if self.quirks.shift {reg} else {bytes[1].rotate_left(4) & 0x0F};
 
-                let lsb = self.memory.reg[reg as usize] & 0b1;
-                self.memory.reg[15] = lsb;
-
-                self.memory.reg[reg as usize] >>= 1;
+                let value = self.memory.reg[src as usize];
+                self.memory.reg[reg as usize] = value >> 1;
+                self.memory.reg[15] = value & 0b1;
             },
             OpcodeTypes::SUBNVxVy => {
                 let bytes = self.opcode.code.to_be_bytes();
                 let reg1 = bytes[0] & 0x0F;
                 let reg2 = bytes[1].rotate_left(4) & 0x0F;
 
-                self.memory.reg[15] = (self.memory.reg[reg1 as usize] > self.memory.reg[reg2 as usize]) as u8;
+                let (difference, borrow) = self.memory.reg[reg2 as usize].overflowing_sub(self.memory.reg[reg1 as usize]);
 
-                let bor = u16::from_be_bytes([self.memory.reg[15], self.memory.reg[reg2 as usize]]);
-                self.memory.reg[reg1 as usize] = (bor - self.memory.reg[reg1 as usize] as u16) as u8
+                self.memory.reg[reg1 as usize] = difference;
+                self.memory.reg[15] = !borrow as u8;
             },
             OpcodeTypes::SHLVxVy => {
                 let bytes = self.opcode.code.to_be_bytes();
                 let reg = bytes[0] & 0x0F;
+                let src = if self.quirks.shift {reg} else {bytes[1].rotate_left(4) & 0x0F};
 
-                let msb = self.memory.reg[reg as usize] & 0b10000000;
-                self.memory.reg[15] = msb;
-
-                self.memory.reg[reg as usize] <<= 1;
+                let value = self.memory.reg[src as usize];
+                self.memory.reg[reg as usize] = value << 1;
+                self.memory.reg[15] = value >> 7;
             },
             OpcodeTypes::SNEVxVy => {
                 let bytes = self.opcode.code.to_be_bytes();
@@ -261,7 +398,10 @@ impl Cpu{
                 let reg2 = bytes[1].rotate_left(4) & 0x0F;
 
                 if self.memory.reg[reg1 as usize] != self.memory.reg[reg2 as usize]{
-                    self.memory.pc += 2;
+                    self.skip()?;
+                }
+                else{
+                    self.memory.pc = self.memory.pc.wrapping_add(2);
                 }
             },
             OpcodeTypes::LDIAddr => {
@@ -270,14 +410,16 @@ impl Cpu{
             },
             OpcodeTypes::JPV0Addr => {
                 let addr = self.opcode.code & 0xFFF;
-                self.memory.pc = self.memory.reg[0] as u16 + addr;
+                let reg = if self.quirks.jump {self.opcode.code.to_be_bytes()[0] & 0x0F} else {0};
+
+                self.memory.pc = self.memory.reg[reg as usize] as u16 + addr;
             },
             OpcodeTypes::RNDVxbyte => {
                 let bytes = self.opcode.code.to_be_bytes();
                 let reg = bytes[0] & 0x0F;
                 let byte = bytes[1];
 
-                let random_byte: u8 = rand::thread_rng().gen();
+                let random_byte = self.rng.next_byte();
 
                 self.memory.reg[reg as usize] = random_byte & byte;
             },
@@ -288,58 +430,51 @@ impl Cpu{
                 let n = bytes[1] & 0x0F;
                 let x_coord = self.memory.reg[reg_x as usize];
                 let y_coord = self.memory.reg[reg_y as usize];
-                
-                self.memory.reg[15] = 0;
-
-                for row in 0..n{
-                    let byte = self.memory.addr_mem[(self.memory.i + row as u16) as usize];
-                    let rel_y_coord = y_coord + row;
-
-                    for pixel in 0..8_u8{
-                        let bit = byte & (0x1 << 7 - pixel);
-                        let rel_x_coord = x_coord + pixel;
-                        
-                        if bit != 0{
-                            if self.curr_buffer[rel_y_coord as usize][rel_x_coord as usize] == 0{
-                                self.curr_buffer[rel_y_coord as usize][rel_x_coord as usize] = 0xFFFFFF
-                            }
-
-                            else{
-                                self.curr_buffer[rel_y_coord as usize][rel_x_coord as usize] = 0;
-                                self.memory.reg[15] = 1;
-                            }
-                        }
-                        if rel_x_coord == 63{
-                            break;
-                        }
-                    }
-                    if rel_y_coord == 31{
-                        break;
+
+                let width = if n == 0 {16} else {8};
+                let row_count = if n == 0 {16} else {n as u16};
+                let plane_len = if n == 0 {32} else {n as u16};
+
+                let mut collision = false;
+                let mut addr = self.memory.i as usize;
+
+                for plane_bit in [1_u8, 2]{
+                    if self.curr_buffer.plane & plane_bit == 0{
+                        continue;
                     }
-                }
 
-                let mut flattened_buffer = [0_u32; 2048];
-                for row in 0..32_u16{
-                    let start_index = row * 64;
-                    let end_index = start_index + 64;
+                    let rows = (0..row_count as usize).map(|row| {
+                        if n == 0{
+                            let at = addr + row * 2;
+                            Ok(u16::from_be_bytes([self.read_mem(at)?, self.read_mem(at + 1)?]))
+                        }
+                        else{
+                            Ok(self.read_mem(addr + row)? as u16)
+                        }
+                    }).collect::<Result<Vec<u16>, EmulatorError>>()?;
 
-                    flattened_buffer[start_index as usize..end_index as usize].copy_from_slice(&self.curr_buffer[row as usize][..]);
+                    collision |= self.curr_buffer.draw_sprite(x_coord as usize, y_coord as usize, width, &rows, plane_bit, self.quirks.wrap);
+                    addr += plane_len as usize;
                 }
 
-                self.window.update_with_buffer(&flattened_buffer, 64, 32).unwrap();
+                self.memory.reg[15] = collision as u8;
+                self.vblank_wait = self.quirks.display_wait;
+
+                self.draw_flag = true;
             },
             OpcodeTypes::SKPVx => {
                 let bytes = self.opcode.code.to_be_bytes();
                 let reg = bytes[0] & 0x0F;
 
                 let key_as_chip8 = self.memory.reg[reg as usize];
-                let real_key = self.key_map.iter().find_map(|(key, &val)| if val == key_as_chip8 {Some(key)} else {None});
+                let pressed = *self.keypad.get(key_as_chip8 as usize)
+                    .ok_or(EmulatorError::InvalidKey {pc : self.memory.pc, opcode : self.opcode.code, key : key_as_chip8})?;
 
-                if self.window.is_key_down(*real_key.expect("Vx did not contain a Key value")){
-                    self.memory.pc += 4;
+                if pressed{
+                    self.skip()?;
                 }
                 else {
-                    self.memory.pc += 2;
+                    self.memory.pc = self.memory.pc.wrapping_add(2);
                 }
             },
             OpcodeTypes::SKNPVx => {
@@ -347,14 +482,15 @@ impl Cpu{
                 let reg = bytes[0] & 0x0F;
 
                 let key_as_chip8 = self.memory.reg[reg as usize];
-                let real_key = self.key_map.iter().find_map(|(key, &val)| if val == key_as_chip8 {Some(key)} else {None});
+                let pressed = *self.keypad.get(key_as_chip8 as usize)
+                    .ok_or(EmulatorError::InvalidKey {pc : self.memory.pc, opcode : self.opcode.code, key : key_as_chip8})?;
 
-                if !self.window.is_key_down(*real_key.expect("Vx did not contain a Key value")){
-                    self.memory.pc += 4
+                if !pressed{
+                    self.skip()?;
                 }
 
                 else {
-                    self.memory.pc += 2
+                    self.memory.pc = self.memory.pc.wrapping_add(2);
                 }
             },
             OpcodeTypes::LDVxDT => {
@@ -366,9 +502,12 @@ impl Cpu{
             OpcodeTypes::LDVxK => {
                 let bytes = self.opcode.code.to_be_bytes();
                 let reg = bytes[0] & 0x0F;
-                
 
-                self.memory.reg[reg as usize] = self.key_map[&self.key[0]];
+                match self.key.first(){
+                    Some(&key) => self.memory.reg[reg as usize] = key,
+                    // No key yet, so run this instruction again once the pc is advanced
+                    None => self.memory.pc = self.memory.pc.wrapping_sub(2)
+                }
             },
             OpcodeTypes::LDDTVx => {
                 let bytes = self.opcode.code.to_be_bytes();
@@ -386,13 +525,13 @@ impl Cpu{
                 let bytes = self.opcode.code.to_be_bytes();
                 let reg = bytes[0] & 0x0F;
 
-                self.memory.i += self.memory.reg[reg as usize] as u16;
+                self.memory.i = self.memory.i.wrapping_add(self.memory.reg[reg as usize] as u16);
             },
             OpcodeTypes::LDFVx => {
                 let bytes = self.opcode.code.to_be_bytes();
                 let reg = bytes[0] & 0x0F;
 
-                self.memory.i = (self.memory.reg[reg as usize] * 5) as u16
+                self.memory.i = (self.memory.reg[reg as usize] & 0x0F) as u16 * 5
             },
             OpcodeTypes::LDBVx => {
                 let bytes = self.opcode.code.to_be_bytes();
@@ -403,16 +542,20 @@ impl Cpu{
                 let tens = (number / 10) % 10;
                 let ones = number % 10;
 
-                self.memory.addr_mem[self.memory.i as usize] = hundred;
-                self.memory.addr_mem[self.memory.i as usize + 1] = tens;
-                self.memory.addr_mem[self.memory.i as usize + 2] = ones;
+                self.write_mem(self.memory.i as usize, hundred)?;
+                self.write_mem(self.memory.i as usize + 1, tens)?;
+                self.write_mem(self.memory.i as usize + 2, ones)?;
             },
             OpcodeTypes::LDIVx => {
                 let bytes = self.opcode.code.to_be_bytes();
                 let reg = bytes[0] & 0x0F;
 
                 for num in 0..=reg {
-                    self.memory.addr_mem[(self.memory.i + num as u16) as usize] = self.memory.reg[reg as usize];
+                    self.write_mem(self.memory.i as usize + num as usize, self.memory.reg[num as usize])?;
+                }
+
+                if !self.quirks.load_store{
+                    self.memory.i = self.memory.i.wrapping_add(reg as u16 + 1);
                 }
             },
             OpcodeTypes::LDVxI => {
@@ -420,10 +563,118 @@ impl Cpu{
                 let reg = bytes[0] & 0x0F;
 
                 for num in 0..=reg {
-                    self.memory.reg[reg as usize] = self.memory.addr_mem[(self.memory.i + num as u16) as usize];
+                    self.memory.reg[num as usize] = self.read_mem(self.memory.i as usize + num as usize)?;
+                }
+
+                if !self.quirks.load_store{
+                    self.memory.i = self.memory.i.wrapping_add(reg as u16 + 1);
                 }
+            },
+            OpcodeTypes::SCDNibble => {
+                let n = self.opcode.code & 0x000F;
+
+                self.curr_buffer.scroll_down(n as usize);
+                self.draw_flag = true;
+            },
+            OpcodeTypes::SCR => {
+                self.curr_buffer.scroll_right(4);
+                self.draw_flag = true;
+            },
+            OpcodeTypes::SCL => {
+                self.curr_buffer.scroll_left(4);
+                self.draw_flag = true;
+            },
+            OpcodeTypes::EXIT => {
+                self.exited = true;
+            },
+            OpcodeTypes::LOW => {
+                self.curr_buffer.set_hires(false);
+                self.draw_flag = true;
+            },
+            OpcodeTypes::HIGH => {
+                self.curr_buffer.set_hires(true);
+                self.draw_flag = true;
+            },
+            OpcodeTypes::LDHFVx => {
+                let bytes = self.opcode.code.to_be_bytes();
+                let reg = bytes[0] & 0x0F;
+
+                self.memory.i = (BIG_FONT_START + (self.memory.reg[reg as usize] & 0x0F) as usize * 10) as u16
+            },
+            OpcodeTypes::LDRVx => {
+                let bytes = self.opcode.code.to_be_bytes();
+                let reg = (bytes[0] & 0x0F) as usize;
+
+                self.memory.rpl[..=reg].copy_from_slice(&self.memory.reg[..=reg]);
+            },
+            OpcodeTypes::LDVxR => {
+                let bytes = self.opcode.code.to_be_bytes();
+                let reg = (bytes[0] & 0x0F) as usize;
+
+                self.memory.reg[..=reg].copy_from_slice(&self.memory.rpl[..=reg]);
+            },
+            OpcodeTypes::SCUNibble => {
+                let n = self.opcode.code & 0x000F;
+
+                self.curr_buffer.scroll_up(n as usize);
+                self.draw_flag = true;
+            },
+            OpcodeTypes::LDIVxVy => {
+                let bytes = self.opcode.code.to_be_bytes();
+                let reg1 = (bytes[0] & 0x0F) as usize;
+                let reg2 = (bytes[1].rotate_left(4) & 0x0F) as usize;
+
+                for (offset, reg) in Cpu::reg_range(reg1, reg2).enumerate(){
+                    self.write_mem(self.memory.i as usize + offset, self.memory.reg[reg])?;
+                }
+            },
+            OpcodeTypes::LDVxVyI => {
+                let bytes = self.opcode.code.to_be_bytes();
+                let reg1 = (bytes[0] & 0x0F) as usize;
+                let reg2 = (bytes[1].rotate_left(4) & 0x0F) as usize;
+
+                for (offset, reg) in Cpu::reg_range(reg1, reg2).enumerate(){
+                    self.memory.reg[reg] = self.read_mem(self.memory.i as usize + offset)?;
+                }
+            },
+            OpcodeTypes::LDILong => {
+                let addr = self.memory.pc as usize + 2;
+
+                self.memory.i = u16::from_be_bytes([self.peek_mem(addr)?, self.peek_mem(addr + 1)?]);
+                self.memory.pc = self.memory.pc.wrapping_add(2);
+            },
+            OpcodeTypes::PLANENibble => {
+                let bytes = self.opcode.code.to_be_bytes();
+
+                self.curr_buffer.plane = bytes[0] & 0x03;
+            },
+            OpcodeTypes::AUDIO => {
+                let start = self.memory.i as usize;
+
+                for offset in 0..16{
+                    self.memory.pattern[offset] = self.read_mem(start + offset)?;
+                }
+                self.memory.pattern_loaded = true;
+            },
+            OpcodeTypes::LDPITCHVx => {
+                let bytes = self.opcode.code.to_be_bytes();
+                let reg = bytes[0] & 0x0F;
+
+                self.memory.pitch = self.memory.reg[reg as usize];
             }
         }
+
+        Ok(())
+    }
+
+    /// Registers from `first` to `last` inclusive, counting down when `first` is the larger one
+    fn reg_range(first: usize, last: usize) -> Box<dyn Iterator<Item = usize>>{
+        if first <= last{
+            Box::new(first..=last)
+        }
+        else{
+            Box::new((last..=first).rev())
+        }
     }
 
     pub fn reset(&mut self){
@@ -434,9 +685,221 @@ impl Cpu{
         self.memory.sp = 0;
         self.memory.delay = 0;
         self.memory.sound = 0;
-        self.key = [].to_vec();
+        self.key = Vec::new();
 
-        self.curr_buffer = [[0;64];32];
-        self.window.update_with_buffer(&[0; 2048], 64, 32).unwrap();
+        self.curr_buffer = Display::new();
+        self.draw_flag = true;
+        self.exited = false;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests{
+    use super::Cpu;
+    use crate::quirks::Quirks;
+    use crate::error::EmulatorError;
+    use crate::memory::{CHIP8_MEMORY_SIZE, MEMORY_SIZE};
+
+    #[test]
+    fn draws_without_window(){
+        // LD V0, 0x0 ; LD F, V0 ; DRW V0, V0, 5
+        let mut processor = Cpu::new(&[0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05]).unwrap();
+        processor.run(3).unwrap();
+
+        let buffer = processor.buffer();
+        assert_eq!(buffer[0..4], [0xFFFFFF; 4]);
+        assert_eq!(buffer[64..68], [0xFFFFFF, 0, 0, 0xFFFFFF]);
+        assert!(processor.draw_flag);
+    }
+
+    #[test]
+    fn cls_clears_buffer(){
+        let mut processor = Cpu::new(&[0x00, 0xE0]).unwrap();
+        processor.curr_buffer.pixels[3 * 64 + 7] = 1;
+        processor.run(1).unwrap();
+
+        assert!(processor.buffer().iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn call_and_ret(){
+        // CALL 0x206 ; LD V1, 0x01 ; JP 0x208 ; LD V0, 0x07 ; RET
+        let mut processor = Cpu::new(&[0x22, 0x06, 0x61, 0x01, 0x12, 0x0A, 0x60, 0x07, 0x00, 0xEE, 0x12, 0x0A]).unwrap();
+        processor.run(5).unwrap();
+
+        assert_eq!(processor.memory.reg[0], 0x07);
+        assert_eq!(processor.memory.reg[1], 0x01);
+        assert_eq!(processor.memory.pc, 0x20A);
+        assert_eq!(processor.memory.sp, 0);
+    }
+
+    #[test]
+    fn schip_hires_big_sprite(){
+        // HIGH ; LD V0, 0x78 ; LD I, 0x20E ; DRW V0, V0, 0 ; EXIT ; CLS ; 16x16 sprite
+        let mut rom = vec![0x00, 0xFF, 0x60, 0x78, 0xA2, 0x0E, 0xD0, 0x00, 0x00, 0xFD, 0x00, 0xE0, 0x00, 0x00];
+        rom.extend([0xFF; 32]);
+        let mut processor = Cpu::new(&rom).unwrap();
+        processor.quirks = Quirks::schip();
+        processor.run(10).unwrap();
+
+        assert!(processor.exited);
+        assert_eq!((processor.curr_buffer.width, processor.curr_buffer.height), (128, 64));
+        assert_eq!(processor.curr_buffer.get(120, 120 - 64), 1);
+        assert_eq!(processor.curr_buffer.get(127, 120 - 64 + 7), 1);
+        assert_eq!(processor.curr_buffer.get(119, 120 - 64), 0);
+        assert_eq!(processor.memory.pc, 0x20A);
+    }
+
+    #[test]
+    fn xo_chip_planes_and_long_load(){
+        // PLANE 3 ; LD I, long 0x0210 ; SE V0, 0 ; LD I, long 0 ; DRW V0, V0, 1 ; JP 0x20E ; data
+        let rom = [0xF3, 0x01, 0xF0, 0x00, 0x02, 0x10, 0x30, 0x00, 0xF0, 0x00, 0x00, 0x00, 0xD0, 0x01, 0x12, 0x0E, 0xF0, 0x3C];
+        let mut processor = Cpu::with_quirks(&rom, Quirks::xo_chip()).unwrap();
+        processor.run(4).unwrap();
+
+        assert_eq!(processor.memory.i, 0x210);
+        assert_eq!(processor.memory.pc, 0x20E);
+        assert_eq!(processor.curr_buffer.pixels[0..8], [1, 1, 3, 3, 2, 2, 0, 0]);
+    }
+
+    #[test]
+    fn xo_chip_register_ranges(){
+        // LD I, 0x300 ; LD V1, 0x11 ; LD V2, 0x22 ; save V2 - V1 ; load V3 - V4
+        let mut processor = Cpu::with_quirks(&[0xA3, 0x00, 0x61, 0x11, 0x62, 0x22, 0x52, 0x12, 0x53, 0x43], Quirks::xo_chip()).unwrap();
+        processor.run(5).unwrap();
+
+        assert_eq!(processor.memory.addr_mem[0x300..0x302], [0x22, 0x11]);
+        assert_eq!(processor.memory.reg[3..5], [0x22, 0x11]);
+        assert_eq!(processor.memory.i, 0x300);
+    }
+
+    #[test]
+    fn shift_quirk(){
+        // LD V1, 0x81 ; SHL V0, V1
+        let mut processor = Cpu::new(&[0x61, 0x81, 0x80, 0x1E]).unwrap();
+        processor.run(2).unwrap();
+        assert_eq!((processor.memory.reg[0], processor.memory.reg[15]), (0x02, 1));
+
+        let mut processor = Cpu::new(&[0x61, 0x81, 0x80, 0x1E]).unwrap();
+        processor.quirks = Quirks::schip();
+        processor.run(2).unwrap();
+        assert_eq!((processor.memory.reg[0], processor.memory.reg[15]), (0x00, 0));
+    }
+
+    #[test]
+    fn load_store_quirk(){
+        // LD V0, 0x0A ; LD V1, 0x0B ; LD I, 0x300 ; LD [I], V1
+        let rom = [0x60, 0x0A, 0x61, 0x0B, 0xA3, 0x00, 0xF1, 0x55];
+        let mut processor = Cpu::new(&rom).unwrap();
+        processor.run(4).unwrap();
+        assert_eq!(processor.memory.addr_mem[0x300..0x302], [0x0A, 0x0B]);
+        assert_eq!(processor.memory.i, 0x302);
+
+        let mut processor = Cpu::new(&rom).unwrap();
+        processor.quirks = Quirks::schip();
+        processor.run(4).unwrap();
+        assert_eq!(processor.memory.i, 0x300);
+    }
+
+    #[test]
+    fn jump_and_vf_reset_quirks(){
+        // LD V2, 0x04 ; LD VF, 0x01 ; OR V0, V0 ; JP V0, 0x210
+        let rom = [0x62, 0x04, 0x6F, 0x01, 0x80, 0x01, 0xB2, 0x10];
+        let mut processor = Cpu::new(&rom).unwrap();
+        processor.run(4).unwrap();
+        assert_eq!(processor.memory.reg[15], 0);
+        assert_eq!(processor.memory.pc, 0x210);
+
+        let mut processor = Cpu::new(&rom).unwrap();
+        processor.quirks = Quirks::chip48();
+        processor.run(4).unwrap();
+        assert_eq!(processor.memory.reg[15], 1);
+        assert_eq!(processor.memory.pc, 0x214);
+    }
+
+    #[test]
+    fn display_wait_quirk(){
+        // DRW V0, V0, 1 ; LD V1, 0x01
+        let mut processor = Cpu::new(&[0xD0, 0x01, 0x61, 0x01]).unwrap();
+        processor.run(2).unwrap();
+        assert_eq!(processor.memory.reg[1], 0);
+
+        processor.tick_timers();
+        processor.run(1).unwrap();
+        assert_eq!(processor.memory.reg[1], 1);
+    }
+
+    #[test]
+    fn errors_carry_pc_and_opcode(){
+        let mut processor = Cpu::new(&[0x60, 0x01, 0x80, 0x0F]).unwrap();
+        assert_eq!(processor.run(2), Err(EmulatorError::UnknownOpcode {pc : 0x202, opcode : 0x800F}));
+
+        let mut processor = Cpu::new(&[0x00, 0xEE]).unwrap();
+        assert_eq!(processor.step(), Err(EmulatorError::StackUnderflow {pc : 0x200, opcode : 0x00EE}));
+
+        let mut processor = Cpu::new(&[0x22, 0x00]).unwrap();
+        assert_eq!(processor.run(17), Err(EmulatorError::StackOverflow {pc : 0x200, opcode : 0x2200}));
+
+        let mut processor = Cpu::new(&[0x60, 0x10, 0xE0, 0x9E]).unwrap();
+        assert_eq!(processor.run(2), Err(EmulatorError::InvalidKey {pc : 0x202, opcode : 0xE09E, key : 0x10}));
+
+        // LD I, long 0xFFFF ; LD B, V0
+        let mut processor = Cpu::with_quirks(&[0xF0, 0x00, 0xFF, 0xFF, 0xF0, 0x33], Quirks::xo_chip()).unwrap();
+        assert_eq!(processor.run(2), Err(EmulatorError::OutOfBoundsMemory {pc : 0x204, opcode : 0xF033, addr : 0x10000}));
+
+        // LD I, 0xFFF ; LD B, V0
+        let mut processor = Cpu::new(&[0xAF, 0xFF, 0xF0, 0x33]).unwrap();
+        assert_eq!(processor.run(2), Err(EmulatorError::OutOfBoundsMemory {pc : 0x202, opcode : 0xF033, addr : 0x1000}));
+
+        assert_eq!(Cpu::new(&vec![0; CHIP8_MEMORY_SIZE - 511]).err(), Some(EmulatorError::RomTooLarge {size : 3585, max : 3584}));
+        assert!(Cpu::with_quirks(&vec![0; CHIP8_MEMORY_SIZE], Quirks::xo_chip()).is_ok());
+        assert!(matches!(Cpu::with_quirks(&vec![0; MEMORY_SIZE], Quirks::xo_chip()), Err(EmulatorError::RomTooLarge {..})));
+        assert!(matches!(Cpu::initialize("TestRoms/missing.ch8"), Err(EmulatorError::Io(err)) if err.starts_with("Cannot read TestRoms/missing.ch8: ")));
+    }
+
+    #[test]
+    fn extensions_need_their_platform(){
+        // SE V0, 0 ; LD I, long 0x1234 ; save V1 - V2
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x51, 0x22];
+        let mut processor = Cpu::new(&rom).unwrap();
+        processor.run(1).unwrap();
+        assert_eq!(processor.memory.pc, 0x204);
+        processor.memory.pc = 0x202;
+        assert_eq!(processor.step(), Err(EmulatorError::UnknownOpcode {pc : 0x202, opcode : 0xF000}));
+
+        let mut processor = Cpu::with_quirks(&rom, Quirks::schip()).unwrap();
+        processor.memory.pc = 0x206;
+        assert_eq!(processor.step(), Err(EmulatorError::UnknownOpcode {pc : 0x206, opcode : 0x5122}));
+        // HIGH runs on SCHIP but not on the VIP
+        assert_eq!(Cpu::new(&[0x00, 0xFF]).unwrap().step(), Err(EmulatorError::UnknownOpcode {pc : 0x200, opcode : 0x00FF}));
+
+        let mut processor = Cpu::with_quirks(&rom, Quirks::xo_chip()).unwrap();
+        processor.run(2).unwrap();
+        assert_eq!(processor.memory.pc, 0x208);
+    }
+
+    #[test]
+    fn keypad_queues_new_presses(){
+        let mut processor = Cpu::new(&[]).unwrap();
+        let mut keypad = [false; 16];
+        keypad[0xA] = true;
+        processor.set_keypad(keypad);
+        assert_eq!(processor.key, vec![0xA]);
+
+        keypad[0x3] = true;
+        processor.set_keypad(keypad);
+        assert_eq!(processor.key, vec![0x3]);
+        assert!(processor.keypad[0xA] && processor.keypad[0x3]);
+    }
+
+    #[test]
+    fn timers_stop_at_zero(){
+        let mut processor = Cpu::new(&[]).unwrap();
+        processor.memory.delay = 2;
+        processor.memory.sound = 1;
+        processor.tick_timers();
+        processor.tick_timers();
+
+        assert_eq!((processor.memory.delay, processor.memory.sound), (0, 0));
+    }
+}
@@ -0,0 +1,494 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use crate::cpu::Cpu;
+use crate::error::EmulatorError;
+use crate::opcode::{Opcode, OpcodeTypes};
+use crate::rewind::Rewind;
+
+/// Instructions that can be stepped back over
+pub const HISTORY_LEN: usize = 4096;
+
+#[derive(Debug, PartialEq)]
+pub enum StopReason{
+    Breakpoint(u16),
+    /// About to execute an instruction of a watched kind
+    Opcode(OpcodeTypes),
+    /// The last instruction read or wrote a watched address
+    Watch {addr : usize, write : bool},
+    /// The last instruction changed a watched register from the first value to the second
+    Register(u8, u8, u8),
+    Stepped,
+    /// A step over or step out got back to the caller
+    Returned,
+    /// Went back this many instructions, fewer than asked when the history ran out
+    Rewound(usize),
+    Exited
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command{
+    Step(usize),
+    ReverseStep(usize),
+    StepOver,
+    StepOut,
+    Continue,
+    Break(u16),
+    Delete(u16),
+    BreakOpcode(OpcodeTypes),
+    Watch(Watchpoint),
+    WatchRegister(u8),
+    Registers,
+    Memory(u16, u16),
+    Quit
+}
+
+/// Inclusive range of addresses to stop on when read and/or written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watchpoint{
+    pub start : u16,
+    pub end : u16,
+    pub read : bool,
+    pub write : bool
+}
+
+pub struct Debugger{
+    pub breakpoints : BTreeSet<u16>,
+    pub opcode_breaks : Vec<OpcodeTypes>,
+    pub watchpoints : Vec<Watchpoint>,
+    /// Indexes of V registers to stop on when they change
+    pub register_watches : BTreeSet<u8>,
+    pub paused : bool,
+    /// Pause once the stack is back down to this depth
+    return_depth : Option<u8>,
+    /// The instruction execution is paused in front of, so continuing does not stop on it again
+    stopped_at : Option<u16>,
+    /// State before each instruction run through the debugger
+    pub history : Rewind
+}
+
+impl Debugger{
+    pub fn new() -> Debugger{
+        Debugger {breakpoints : BTreeSet::new(), opcode_breaks : Vec::new(), watchpoints : Vec::new(), register_watches : BTreeSet::new(),
+            paused : true, return_depth : None, stopped_at : None, history : Rewind::new(HISTORY_LEN)}
+    }
+
+    pub fn resume(&mut self){
+        self.paused = false;
+    }
+
+    /// Breakpoint and opcode stops happen before the instruction runs, so they are skipped once when continuing
+    fn pause(&mut self, processor: &Cpu, reason: StopReason) -> Option<StopReason>{
+        self.paused = true;
+        self.stopped_at = match reason{
+            StopReason::Breakpoint(_) | StopReason::Opcode(_) => Some(processor.memory.pc),
+            _ => None
+        };
+
+        Some(reason)
+    }
+
+    /// Executes exactly one instruction and stays paused. A machine waiting for the display has its frame ended
+    /// first, otherwise the instruction would never run while paused.
+    pub fn step(&mut self, processor: &mut Cpu) -> Result<StopReason, EmulatorError>{
+        if processor.vblank_wait{
+            processor.tick_timers();
+        }
+        self.execute(processor)?;
+        self.paused = true;
+        self.stopped_at = Some(processor.memory.pc);
+
+        Ok(if processor.exited {StopReason::Exited} else {StopReason::Stepped})
+    }
+
+    /// Steps, running a whole subroutine if the instruction is a CALL
+    pub fn step_over(&mut self, processor: &mut Cpu) -> Result<StopReason, EmulatorError>{
+        let depth = processor.memory.sp;
+        let reason = self.step(processor)?;
+
+        if processor.memory.sp > depth{
+            self.return_depth = Some(depth);
+            self.resume();
+        }
+
+        Ok(reason)
+    }
+
+    /// Runs until the current subroutine returns
+    pub fn step_out(&mut self, processor: &Cpu){
+        if processor.memory.sp > 0{
+            self.return_depth = Some(processor.memory.sp - 1);
+        }
+
+        self.resume();
+    }
+
+    /// Runs one instruction unless paused, returns why execution stopped if it did
+    pub fn run_step(&mut self, processor: &mut Cpu) -> Result<Option<StopReason>, EmulatorError>{
+        if self.paused{
+            return Ok(None);
+        }
+
+        let pc = processor.memory.pc;
+        if self.stopped_at.take() != Some(pc){
+            if self.breakpoints.contains(&pc){
+                return Ok(self.pause(processor, StopReason::Breakpoint(pc)));
+            }
+
+            if let Some(kind) = self.next_kind(processor).filter(|kind| self.opcode_breaks.contains(kind)){
+                return Ok(self.pause(processor, StopReason::Opcode(kind)));
+            }
+        }
+
+        let reg = processor.memory.reg;
+        self.execute(processor)?;
+
+        if processor.exited{
+            return Ok(self.pause(processor, StopReason::Exited));
+        }
+
+        if let Some(&access) = processor.mem_accesses.iter().find(|access| self.watchpoints.iter().any(|watch| watch.matches(access.addr, access.write))){
+            return Ok(self.pause(processor, StopReason::Watch {addr : access.addr, write : access.write}));
+        }
+
+        if let Some(&num) = self.register_watches.iter().find(|&&num| reg[num as usize] != processor.memory.reg[num as usize]){
+            return Ok(self.pause(processor, StopReason::Register(num, reg[num as usize], processor.memory.reg[num as usize])));
+        }
+
+        if let Some(depth) = self.return_depth{
+            if processor.memory.sp <= depth{
+                self.return_depth = None;
+                return Ok(self.pause(processor, StopReason::Returned));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Runs the next instruction. When it fails the machine is put back as it was before it and execution pauses
+    /// in front of it, so the failure can be looked into.
+    fn execute(&mut self, processor: &mut Cpu) -> Result<(), EmulatorError>{
+        self.record(processor);
+        if let Err(err) = processor.step(){
+            self.history.rewind(processor);
+            self.paused = true;
+            self.stopped_at = Some(processor.memory.pc);
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    fn record(&mut self, processor: &Cpu){
+        if !processor.exited && !processor.vblank_wait{
+            self.history.push(processor);
+        }
+    }
+
+    /// Undoes up to `count` instructions and stays paused
+    pub fn reverse_step(&mut self, processor: &mut Cpu, count: usize) -> StopReason{
+        let rewound = (0..count).take_while(|_| self.history.rewind(processor)).count();
+        self.paused = true;
+        self.stopped_at = Some(processor.memory.pc);
+
+        StopReason::Rewound(rewound)
+    }
+
+    fn next_kind(&self, processor: &Cpu) -> Option<OpcodeTypes>{
+        let pc = processor.memory.pc as usize;
+        let bytes = processor.memory.addr_mem.get(pc..pc + 2)?;
+
+        Opcode::find_kind(u16::from_be_bytes([bytes[0], bytes[1]])).ok()
+    }
+
+    /// Applies a command, returning the reason for stopping when it executed instructions
+    pub fn apply(&mut self, command: &Command, processor: &mut Cpu) -> Result<Option<StopReason>, EmulatorError>{
+        match command{
+            Command::Step(count) => {
+                let mut reason = StopReason::Stepped;
+                for _ in 0..*count{
+                    reason = self.step(processor)?;
+                    if reason == StopReason::Exited || self.breakpoints.contains(&processor.memory.pc){
+                        break;
+                    }
+                }
+                Ok(Some(reason))
+            },
+            Command::ReverseStep(count) => Ok(Some(self.reverse_step(processor, *count))),
+            Command::StepOver => self.step_over(processor).map(Some),
+            Command::StepOut => {
+                self.step_out(processor);
+                Ok(None)
+            },
+            Command::Continue => {
+                self.resume();
+                Ok(None)
+            },
+            Command::Break(addr) => {
+                self.breakpoints.insert(*addr);
+                Ok(None)
+            },
+            Command::Delete(addr) => {
+                self.breakpoints.remove(addr);
+                Ok(None)
+            },
+            Command::BreakOpcode(kind) => {
+                self.opcode_breaks.push(*kind);
+                Ok(None)
+            },
+            Command::Watch(watch) => {
+                self.watchpoints.push(*watch);
+                Ok(None)
+            },
+            Command::WatchRegister(num) => {
+                self.register_watches.insert(*num);
+                Ok(None)
+            },
+            Command::Registers | Command::Memory(..) | Command::Quit => Ok(None)
+        }
+    }
+}
+
+impl Default for Debugger{
+    fn default() -> Self{
+        Debugger::new()
+    }
+}
+
+impl Watchpoint{
+    pub fn matches(&self, addr: usize, write: bool) -> bool{
+        (self.start as usize..=self.end as usize).contains(&addr) && if write {self.write} else {self.read}
+    }
+
+    /// Parses `ADDR[-END] [r|w|rw]`, watching writes when no mode is given
+    pub fn parse(range: Option<&str>, mode: Option<&str>) -> Result<Watchpoint, String>{
+        let range = range.ok_or("Missing address")?;
+        let (start, end) = match range.split_once('-'){
+            Some((start, end)) => (parse_addr(Some(start))?, parse_addr(Some(end))?),
+            None => (parse_addr(Some(range))?, parse_addr(Some(range))?)
+        };
+
+        let (read, write) = match mode.unwrap_or("w"){
+            "r" => (true, false),
+            "w" => (false, true),
+            "rw" => (true, true),
+            other => return Err(format!("Bad watch mode {}", other))
+        };
+
+        Ok(Watchpoint {start : start.min(end), end : start.max(end), read, write})
+    }
+}
+
+impl Command{
+    pub fn parse(line: &str) -> Result<Command, String>{
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or("step");
+        let arg = words.next();
+
+        match name{
+            "s" | "step" => Ok(Command::Step(match arg {Some(count) => count.parse().map_err(|_| format!("Bad count {}", count))?, None => 1})),
+            "rs" | "reverse-step" => Ok(Command::ReverseStep(match arg {Some(count) => count.parse().map_err(|_| format!("Bad count {}", count))?, None => 1})),
+            "n" | "next" | "over" => Ok(Command::StepOver),
+            "o" | "out" | "finish" => Ok(Command::StepOut),
+            "c" | "continue" => Ok(Command::Continue),
+            "b" | "break" => Ok(Command::Break(parse_addr(arg)?)),
+            "d" | "delete" => Ok(Command::Delete(parse_addr(arg)?)),
+            "bo" | "breakop" => Ok(Command::BreakOpcode(arg.ok_or("Missing opcode kind")?.parse()?)),
+            "w" | "watch" => Ok(Command::Watch(Watchpoint::parse(arg, words.next())?)),
+            "wv" | "watchreg" => Ok(Command::WatchRegister(parse_register(arg)?)),
+            "r" | "regs" => Ok(Command::Registers),
+            "m" | "mem" => Ok(Command::Memory(parse_addr(arg)?, match words.next() {Some(len) => parse_addr(Some(len))?, None => 16})),
+            "q" | "quit" => Ok(Command::Quit),
+            _ => Err(format!("Unknown command {}", name))
+        }
+    }
+}
+
+/// Parses an address in hex, with or without a leading 0x
+pub fn parse_addr(arg: Option<&str>) -> Result<u16, String>{
+    let arg = arg.ok_or("Missing address")?;
+    u16::from_str_radix(arg.trim_start_matches("0x").trim_start_matches("0X"), 16).map_err(|_| format!("Bad address {}", arg))
+}
+
+/// Parses a V register as `V3`, `vA` or just the hex digit
+pub fn parse_register(arg: Option<&str>) -> Result<u8, String>{
+    let arg = arg.ok_or("Missing register")?;
+    let digit = arg.trim_start_matches(['V', 'v']);
+
+    u8::from_str_radix(digit, 16).ok().filter(|&num| num < 16 && digit.len() == 1).ok_or(format!("Bad register {}", arg))
+}
+
+/// V0-VF, I, PC, SP, timers, the stack and the instruction about to run
+pub fn registers(processor: &Cpu) -> String{
+    let memory = &processor.memory;
+    let mut view = String::new();
+
+    for (num, value) in memory.reg.iter().enumerate(){
+        write!(view, "V{:X}={:02X} ", num, value).unwrap();
+        if num == 7{
+            view.push('\n');
+        }
+    }
+
+    writeln!(view, "\nI={:04X} PC={:04X} SP={:X} DT={:02X} ST={:02X}", memory.i, memory.pc, memory.sp, memory.delay, memory.sound).unwrap();
+
+    let stack: Vec<String> = memory.stack.iter().take(memory.sp as usize).map(|addr| format!("{:04X}", addr.unwrap_or(0))).collect();
+    writeln!(view, "Stack: [{}]", stack.join(" ")).unwrap();
+
+    let pc = memory.pc as usize;
+    if let (Some(&high), Some(&low)) = (memory.addr_mem.get(pc), memory.addr_mem.get(pc + 1)){
+        let code = u16::from_be_bytes([high, low]);
+        let name = Opcode::find_kind(code).map_or("???".to_string(), |kind: OpcodeTypes| format!("{:?}", kind));
+        write!(view, "{:04X}: {:04X} {}", pc, code, name).unwrap();
+    }
+
+    view
+}
+
+/// Hex dump of `len` bytes starting at `addr`
+pub fn memory_dump(processor: &Cpu, addr: u16, len: u16) -> String{
+    let start = addr as usize;
+    let end = (start + len as usize).min(processor.memory.addr_mem.len());
+
+    processor.memory.addr_mem[start.min(end)..end].chunks(16).enumerate().map(|(line, bytes)| {
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        format!("{:04X}: {}", start + line * 16, hex.join(" "))
+    }).collect::<Vec<String>>().join("\n")
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{Command, Debugger, StopReason, Watchpoint};
+    use crate::cpu::Cpu;
+    use crate::opcode::OpcodeTypes;
+    use crate::quirks::Quirks;
+
+    // 0x200: CALL 0x208 ; LD V1, 0x01 ; JP 0x206 ;
+    // 0x208: LD V0, 0x07 ; CALL 0x20E ; RET ; 0x20E: LD V2, 0x02 ; RET
+    const ROM: [u8; 18] = [0x22, 0x08, 0x61, 0x01, 0x12, 0x06, 0x00, 0x00, 0x60, 0x07, 0x22, 0x0E, 0x00, 0xEE, 0x62, 0x02, 0x00, 0xEE];
+
+    fn run_until_stop(debugger: &mut Debugger, processor: &mut Cpu) -> StopReason{
+        loop{
+            if let Some(reason) = debugger.run_step(processor).unwrap(){
+                return reason;
+            }
+        }
+    }
+
+    #[test]
+    fn breakpoints_stop_and_continue(){
+        let mut processor = Cpu::new(&ROM).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.breakpoints.insert(0x20E);
+        debugger.resume();
+
+        assert_eq!(run_until_stop(&mut debugger, &mut processor), StopReason::Breakpoint(0x20E));
+        assert_eq!(processor.memory.reg[0], 0x07);
+        assert_eq!(processor.memory.sp, 2);
+
+        debugger.step_out(&processor);
+        assert_eq!(run_until_stop(&mut debugger, &mut processor), StopReason::Returned);
+        assert_eq!(processor.memory.pc, 0x20C);
+        assert_eq!(processor.memory.reg[2], 0x02);
+    }
+
+    #[test]
+    fn step_over_runs_subroutine(){
+        let mut processor = Cpu::new(&ROM).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.step_over(&mut processor).unwrap();
+
+        assert_eq!(run_until_stop(&mut debugger, &mut processor), StopReason::Returned);
+        assert_eq!(processor.memory.pc, 0x202);
+        assert_eq!((processor.memory.reg[0], processor.memory.reg[2]), (0x07, 0x02));
+    }
+
+    #[test]
+    fn steps_past_display_wait(){
+        // DRW V0, V0, 1 ; CALL 0x206 ; JP 0x204 ; 0x206: LD V1, 0x01 ; RET
+        let rom = [0xD0, 0x01, 0x22, 0x06, 0x12, 0x04, 0x61, 0x01, 0x00, 0xEE];
+        let mut processor = Cpu::with_quirks(&rom, Quirks::cosmac_vip()).unwrap();
+        let mut debugger = Debugger::new();
+
+        assert_eq!(debugger.step(&mut processor).unwrap(), StopReason::Stepped);
+        assert!(processor.vblank_wait);
+        debugger.step_over(&mut processor).unwrap();
+        assert_eq!((processor.memory.pc, processor.memory.sp), (0x206, 1));
+        assert_eq!(run_until_stop(&mut debugger, &mut processor), StopReason::Returned);
+        assert_eq!((processor.memory.pc, processor.memory.reg[1]), (0x204, 0x01));
+    }
+
+    #[test]
+    fn errors_leave_machine_paused_before_them(){
+        // LD V0, 0xAA ; LD I, 0xFFE ; LD [I], V3
+        let rom = [0x60, 0xAA, 0xAF, 0xFE, 0xF3, 0x55];
+        let mut processor = Cpu::new(&rom).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.resume();
+
+        assert_eq!(debugger.run_step(&mut processor).unwrap(), None);
+        assert_eq!(debugger.run_step(&mut processor).unwrap(), None);
+        assert!(debugger.run_step(&mut processor).is_err());
+        assert!(debugger.paused);
+        assert_eq!((processor.memory.pc, processor.memory.i, processor.memory.addr_mem[0xFFE]), (0x204, 0xFFE, 0));
+
+        assert!(debugger.apply(&Command::Step(1), &mut processor).is_err());
+        assert_eq!((processor.memory.pc, processor.memory.addr_mem[0xFFE]), (0x204, 0));
+        assert_eq!(debugger.reverse_step(&mut processor, 1), StopReason::Rewound(1));
+        assert_eq!(processor.memory.pc, 0x202);
+    }
+
+    #[test]
+    fn reverse_step_undoes_instructions(){
+        let mut processor = Cpu::new(&ROM).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.step_over(&mut processor).unwrap();
+        run_until_stop(&mut debugger, &mut processor);
+        let executed = debugger.history.len();
+
+        assert_eq!(debugger.apply(&Command::ReverseStep(1), &mut processor), Ok(Some(StopReason::Rewound(1))));
+        assert_ne!(processor.memory.sp, 0);
+
+        assert_eq!(debugger.reverse_step(&mut processor, 100), StopReason::Rewound(executed - 1));
+        assert_eq!((processor.memory.pc, processor.memory.sp, processor.memory.reg[0]), (0x200, 0, 0));
+    }
+
+    #[test]
+    fn watchpoints_and_opcode_breaks(){
+        // LD V0, 0x2A ; LD I, 0x300 ; LD B, V0 ; DRW V0, V0, 1 ; LD V2, [I] ; LD V2, [I]
+        let rom = [0x60, 0x2A, 0xA3, 0x00, 0xF0, 0x33, 0xD0, 0x01, 0xF2, 0x65, 0xF2, 0x65];
+        let mut processor = Cpu::new(&rom).unwrap();
+        processor.quirks = Quirks::schip();
+        let mut debugger = Debugger::new();
+        debugger.watchpoints.push(Watchpoint::parse(Some("301-302"), None).unwrap());
+        debugger.opcode_breaks.push(OpcodeTypes::LDVxI);
+        debugger.register_watches.insert(2);
+        debugger.resume();
+
+        assert_eq!(run_until_stop(&mut debugger, &mut processor), StopReason::Watch {addr : 0x301, write : true});
+        assert_eq!(processor.memory.pc, 0x206);
+
+        debugger.resume();
+        assert_eq!(run_until_stop(&mut debugger, &mut processor), StopReason::Opcode(OpcodeTypes::LDVxI));
+        assert_eq!(processor.memory.pc, 0x208);
+
+        debugger.resume();
+        assert_eq!(run_until_stop(&mut debugger, &mut processor), StopReason::Register(2, 0x00, 0x02));
+
+        debugger.resume();
+        assert_eq!(run_until_stop(&mut debugger, &mut processor), StopReason::Opcode(OpcodeTypes::LDVxI));
+    }
+
+    #[test]
+    fn parses_commands(){
+        assert_eq!(Command::parse("b 0x2A4"), Ok(Command::Break(0x2A4)));
+        assert_eq!(Command::parse("s 5"), Ok(Command::Step(5)));
+        assert_eq!(Command::parse("reverse-step"), Ok(Command::ReverseStep(1)));
+        assert_eq!(Command::parse(""), Ok(Command::Step(1)));
+        assert_eq!(Command::parse("m 200 8"), Ok(Command::Memory(0x200, 8)));
+        assert!(Command::parse("b").is_err());
+        assert_eq!(Command::parse("bo LDIVx"), Ok(Command::BreakOpcode(OpcodeTypes::LDIVx)));
+        assert_eq!(Command::parse("wv VA"), Ok(Command::WatchRegister(0xA)));
+        assert_eq!(Command::parse("w 300-30F rw"), Ok(Command::Watch(Watchpoint {start : 0x300, end : 0x30F, read : true, write : true})));
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use crate::opcode::{Opcode, OpcodeTypes, Platform};
+
+/// Where ROMs are loaded and execution starts
+pub const START: u16 = 0x200;
+
+/// Data bytes are listed this many to a line
+const DATA_PER_LINE: usize = 8;
+
+/// One line of a listing, either an instruction or a run of bytes that are never executed
+#[derive(Debug, PartialEq)]
+pub struct Line{
+    pub addr : u16,
+    pub bytes : Vec<u8>,
+    /// Set when something jumps to or calls this address
+    pub label : Option<String>,
+    pub text : String
+}
+
+impl fmt::Display for Line{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        if let Some(label) = &self.label{
+            writeln!(f, "{}:", label)?;
+        }
+        write!(f, "{:#05X}: {}", self.addr, self.text)
+    }
+}
+
+pub fn label_name(addr: u16) -> String{
+    format!("L{:03X}", addr)
+}
+
+/// Formats a single instruction, `long` is the word after it which only F000 NNNN uses
+pub fn mnemonic(code: u16, long: u16) -> Result<String, String>{
+    Opcode::find_kind(code).map(|kind| format(kind, code, long, &BTreeSet::new()))
+}
+
+/// Turns a ROM loaded at 0x200 into a listing. Only instructions reachable from the start that `platform` supports are
+/// decoded, everything else is listed as `db` data. Fails when the ROM doesn't fit below 0x10000.
+pub fn disassemble(rom: &[u8], platform: Platform) -> Result<Vec<Line>, String>{
+    let max = 0x10000 - START as usize;
+    if rom.len() > max{
+        return Err(format!("ROM is {} bytes, at most {} fit in memory", rom.len(), max));
+    }
+    let (starts, targets) = trace(rom, platform);
+
+    let mut bounds = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len(){
+        let addr = START + offset as u16;
+        match starts.get(&addr){
+            Some(&len) => {
+                bounds.push((addr, len, true));
+                offset += len;
+            },
+            None => {
+                let len = (1..DATA_PER_LINE).find(|&len| offset + len >= rom.len() || starts.contains_key(&(addr + len as u16)))
+                    .unwrap_or(DATA_PER_LINE);
+                bounds.push((addr, len, false));
+                offset += len;
+            }
+        }
+    }
+
+    let labels: BTreeSet<u16> = bounds.iter().filter(|(addr, _, code)| *code && targets.contains(addr)).map(|(addr, _, _)| *addr).collect();
+
+    Ok(bounds.into_iter().map(|(addr, len, code)| {
+        let offset = (addr - START) as usize;
+        let bytes = rom[offset..offset + len].to_vec();
+        let text = if code{
+            let code = u16::from_be_bytes([bytes[0], bytes[1]]);
+            let long = if len == 4 {u16::from_be_bytes([bytes[2], bytes[3]])} else {0};
+            format(Opcode::find_kind(code).unwrap(), code, long, &labels)
+        }
+        else{
+            format!("db {}", bytes.iter().map(|byte| format!("{:#04X}", byte)).collect::<Vec<_>>().join(", "))
+        };
+
+        Line {addr, label : labels.contains(&addr).then(|| label_name(addr)), bytes, text}
+    }).collect())
+}
+
+/// Follows every path from the start, returning the length of each instruction found and the jump and call targets
+fn trace(rom: &[u8], platform: Platform) -> (BTreeMap<u16, usize>, BTreeSet<u16>){
+    let word = |addr: u16| -> Option<u16>{
+        let offset = addr.checked_sub(START)? as usize;
+        Some(u16::from_be_bytes([*rom.get(offset)?, *rom.get(offset + 1)?]))
+    };
+    let decode = |addr: u16| -> Option<(OpcodeTypes, u16, usize)>{
+        let code = word(addr)?;
+        let kind = Opcode::find_kind(code).ok().filter(|kind| kind.platform() <= platform)?;
+        let len = if kind == OpcodeTypes::LDILong {4} else {2};
+        word(addr.checked_add(len as u16 - 2)?)?;
+        Some((kind, code, len))
+    };
+
+    let mut starts = BTreeMap::new();
+    let mut targets = BTreeSet::new();
+    let mut pending = vec![START];
+
+    while let Some(addr) = pending.pop(){
+        if starts.contains_key(&addr){
+            continue;
+        }
+        let Some((kind, code, len)) = decode(addr) else {continue};
+        starts.insert(addr, len);
+
+        let next = addr.wrapping_add(len as u16);
+        match kind{
+            OpcodeTypes::JPAddr => {
+                targets.insert(code & 0xFFF);
+                pending.push(code & 0xFFF);
+            },
+            OpcodeTypes::CALLAddr => {
+                targets.insert(code & 0xFFF);
+                pending.push(code & 0xFFF);
+                pending.push(next);
+            },
+            OpcodeTypes::RET | OpcodeTypes::EXIT | OpcodeTypes::JPV0Addr => {},
+            OpcodeTypes::SEVxByte | OpcodeTypes::SNEVxByte | OpcodeTypes::SEVxVy | OpcodeTypes::SNEVxVy |
+            OpcodeTypes::SKPVx | OpcodeTypes::SKNPVx => {
+                let skipped = if platform >= Platform::XoChip && word(next) == Some(0xF000) {4} else {2};
+                pending.push(next);
+                pending.push(next.wrapping_add(skipped));
+            },
+            _ => pending.push(next)
+        }
+    }
+
+    (starts, targets)
+}
+
+fn format(kind: OpcodeTypes, code: u16, long: u16, labels: &BTreeSet<u16>) -> String{
+    let x = (code & 0x0F00) >> 8;
+    let y = (code & 0x00F0) >> 4;
+    let n = code & 0x000F;
+    let kk = code & 0x00FF;
+    let nnn = code & 0x0FFF;
+    let target = if labels.contains(&nnn) {label_name(nnn)} else {format!("{:#05X}", nnn)};
+
+    match kind{
+        OpcodeTypes::CLS => "CLS".to_string(),
+        OpcodeTypes::RET => "RET".to_string(),
+        OpcodeTypes::JPAddr => format!("JP {}", target),
+        OpcodeTypes::CALLAddr => format!("CALL {}", target),
+        OpcodeTypes::SEVxByte => format!("SE V{:X}, {:#04X}", x, kk),
+        OpcodeTypes::SNEVxByte => format!("SNE V{:X}, {:#04X}", x, kk),
+        OpcodeTypes::SEVxVy => format!("SE V{:X}, V{:X}", x, y),
+        OpcodeTypes::LDVxbyte => format!("LD V{:X}, {:#04X}", x, kk),
+        OpcodeTypes::ADDVxbyte => format!("ADD V{:X}, {:#04X}", x, kk),
+        OpcodeTypes::LDVxVy => format!("LD V{:X}, V{:X}", x, y),
+        OpcodeTypes::ORVxVy => format!("OR V{:X}, V{:X}", x, y),
+        OpcodeTypes::ANDVxVy => format!("AND V{:X}, V{:X}", x, y),
+        OpcodeTypes::XORVxVy => format!("XOR V{:X}, V{:X}", x, y),
+        OpcodeTypes::ADDVxVy => format!("ADD V{:X}, V{:X}", x, y),
+        OpcodeTypes::SUBVxVy => format!("SUB V{:X}, V{:X}", x, y),
+        OpcodeTypes::SHRVxVy => format!("SHR V{:X}, V{:X}", x, y),
+        OpcodeTypes::SUBNVxVy => format!("SUBN V{:X}, V{:X}", x, y),
+        OpcodeTypes::SHLVxVy => format!("SHL V{:X}, V{:X}", x, y),
+        OpcodeTypes::SNEVxVy => format!("SNE V{:X}, V{:X}", x, y),
+        OpcodeTypes::LDIAddr => format!("LD I, {:#05X}", nnn),
+        OpcodeTypes::JPV0Addr => format!("JP V0, {:#05X}", nnn),
+        OpcodeTypes::RNDVxbyte => format!("RND V{:X}, {:#04X}", x, kk),
+        OpcodeTypes::DRWVxVyNibble => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        OpcodeTypes::SKPVx => format!("SKP V{:X}", x),
+        OpcodeTypes::SKNPVx => format!("SKNP V{:X}", x),
+        OpcodeTypes::LDVxDT => format!("LD V{:X}, DT", x),
+        OpcodeTypes::LDVxK => format!("LD V{:X}, K", x),
+        OpcodeTypes::LDDTVx => format!("LD DT, V{:X}", x),
+        OpcodeTypes::LDSTVx => format!("LD ST, V{:X}", x),
+        OpcodeTypes::ADDIVx => format!("ADD I, V{:X}", x),
+        OpcodeTypes::LDFVx => format!("LD F, V{:X}", x),
+        OpcodeTypes::LDBVx => format!("LD B, V{:X}", x),
+        OpcodeTypes::LDIVx => format!("LD [I], V{:X}", x),
+        OpcodeTypes::LDVxI => format!("LD V{:X}, [I]", x),
+        OpcodeTypes::SCDNibble => format!("SCD {}", n),
+        OpcodeTypes::SCR => "SCR".to_string(),
+        OpcodeTypes::SCL => "SCL".to_string(),
+        OpcodeTypes::EXIT => "EXIT".to_string(),
+        OpcodeTypes::LOW => "LOW".to_string(),
+        OpcodeTypes::HIGH => "HIGH".to_string(),
+        OpcodeTypes::LDHFVx => format!("LD HF, V{:X}", x),
+        OpcodeTypes::LDRVx => format!("LD R, V{:X}", x),
+        OpcodeTypes::LDVxR => format!("LD V{:X}, R", x),
+        OpcodeTypes::SCUNibble => format!("SCU {}", n),
+        OpcodeTypes::LDIVxVy => format!("LD [I], V{:X}, V{:X}", x, y),
+        OpcodeTypes::LDVxVyI => format!("LD V{:X}, V{:X}, [I]", x, y),
+        OpcodeTypes::LDILong => format!("LD I, LONG {:#06X}", long),
+        OpcodeTypes::PLANENibble => format!("PLANE {}", x),
+        OpcodeTypes::AUDIO => "AUDIO".to_string(),
+        OpcodeTypes::LDPITCHVx => format!("LD PITCH, V{:X}", x)
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{disassemble, mnemonic};
+    use crate::opcode::Platform;
+
+    fn listing(rom: &[u8], platform: Platform) -> Vec<String>{
+        disassemble(rom, platform).unwrap().iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn labels_and_data(){
+        // CALL 0x206 ; JP 0x202 ; two bytes of sprite data ; LD I, 0x204 ; RET
+        let rom = [0x22, 0x06, 0x12, 0x02, 0xF0, 0x90, 0xA2, 0x04, 0x00, 0xEE];
+
+        assert_eq!(listing(&rom, Platform::Chip8), [
+            "0x200: CALL L206",
+            "L202:\n0x202: JP L202",
+            "0x204: db 0xF0, 0x90",
+            "L206:\n0x206: LD I, 0x204",
+            "0x208: RET"
+        ]);
+    }
+
+    #[test]
+    fn extensions_only_where_enabled(){
+        // HIGH ; SE V0, 0 ; LD I, LONG 0x1234 ; EXIT
+        let rom = [0x00, 0xFF, 0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xFD];
+
+        assert_eq!(listing(&rom, Platform::Chip8), ["0x200: db 0x00, 0xFF, 0x30, 0x00, 0xF0, 0x00, 0x12, 0x34", "0x208: db 0x00, 0xFD"]);
+        assert_eq!(listing(&rom, Platform::XoChip), ["0x200: HIGH", "0x202: SE V0, 0x00", "0x204: LD I, LONG 0x1234", "0x208: EXIT"]);
+        assert_eq!(mnemonic(0xD12F, 0), Ok("DRW V1, V2, 15".to_string()));
+    }
+
+    #[test]
+    fn rejects_roms_past_the_end_of_memory(){
+        assert_eq!(disassemble(&[0; 0xFE00], Platform::XoChip).unwrap().last().unwrap().addr, 0xFFF8);
+        assert_eq!(disassemble(&[0; 0xFE01], Platform::XoChip).unwrap_err(), "ROM is 65025 bytes, at most 65024 fit in memory");
+    }
+}
@@ -0,0 +1,191 @@
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+/// Background, plane 1, plane 2 and both planes
+pub const DEFAULT_PALETTE: [u32; 4] = [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555];
+
+pub struct Display{
+    pub width : usize,
+    pub height : usize,
+    pub hires : bool,
+    /// One entry per pixel in row-major order, bit 0 is plane 1 and bit 1 is plane 2
+    pub pixels : Vec<u8>,
+    /// XO-CHIP bitmask of the planes that drawing, clearing and scrolling act on
+    pub plane : u8,
+    pub palette : [u32; 4]
+}
+
+impl Display{
+    pub fn new() -> Display{
+        Display {width : LORES_WIDTH, height : LORES_HEIGHT, hires : false, pixels : vec![0; LORES_WIDTH * LORES_HEIGHT], plane : 1, palette : DEFAULT_PALETTE}
+    }
+
+    /// Clears the selected planes
+    pub fn clear(&mut self){
+        let keep = !self.plane;
+        self.pixels.iter_mut().for_each(|pixel| *pixel &= keep);
+    }
+
+    /// Switches between 64x32 and 128x64, which also clears the screen
+    pub fn set_hires(&mut self, hires: bool){
+        let (width, height) = if hires {(HIRES_WIDTH, HIRES_HEIGHT)} else {(LORES_WIDTH, LORES_HEIGHT)};
+
+        self.hires = hires;
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![0; width * height];
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u8{
+        self.pixels[y * self.width + x]
+    }
+
+    /// XORs a sprite onto the plane given by the `plane_bit` mask, `width` is 8 or 16 pixels and each row is that many bits of `rows`.
+    /// The origin always wraps around the screen, the rest of the sprite is clipped unless `wrap` is set. Returns true on collision.
+    pub fn draw_sprite(&mut self, x: usize, y: usize, width: usize, rows: &[u16], plane_bit: u8, wrap: bool) -> bool{
+        let x = x % self.width;
+        let y = y % self.height;
+        let mut collision = false;
+
+        for (row, bits) in rows.iter().enumerate(){
+            let mut rel_y_coord = y + row;
+            if rel_y_coord >= self.height{
+                if !wrap{
+                    break;
+                }
+                rel_y_coord %= self.height;
+            }
+
+            for pixel in 0..width{
+                let mut rel_x_coord = x + pixel;
+                if rel_x_coord >= self.width{
+                    if !wrap{
+                        break;
+                    }
+                    rel_x_coord %= self.width;
+                }
+
+                if bits & (0x1 << (width - 1 - pixel)) != 0{
+                    let index = rel_y_coord * self.width + rel_x_coord;
+                    if self.pixels[index] & plane_bit != 0{
+                        collision = true;
+                    }
+                    self.pixels[index] ^= plane_bit;
+                }
+            }
+        }
+
+        collision
+    }
+
+    /// Moves the selected planes by `dx`, `dy` pixels, filling with blank pixels
+    fn shift(&mut self, dx: isize, dy: isize){
+        let plane = self.plane;
+        let old = self.pixels.clone();
+
+        for y in 0..self.height{
+            for x in 0..self.width{
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+                let moved = if src_x >= 0 && src_y >= 0 && (src_x as usize) < self.width && (src_y as usize) < self.height{
+                    old[src_y as usize * self.width + src_x as usize] & plane
+                }
+                else{
+                    0
+                };
+
+                let index = y * self.width + x;
+                self.pixels[index] = (old[index] & !plane) | moved;
+            }
+        }
+    }
+
+    pub fn scroll_down(&mut self, rows: usize){
+        self.shift(0, rows as isize);
+    }
+
+    /// Scrolls up, the XO-CHIP 00DN instruction
+    pub fn scroll_up(&mut self, rows: usize){
+        self.shift(0, -(rows as isize));
+    }
+
+    pub fn scroll_right(&mut self, columns: usize){
+        self.shift(columns as isize, 0);
+    }
+
+    pub fn scroll_left(&mut self, columns: usize){
+        self.shift(-(columns as isize), 0);
+    }
+
+    /// Returns the screen as row-major 0RGB pixels coloured with the palette
+    pub fn to_rgb(&self) -> Vec<u32>{
+        self.pixels.iter().map(|&pixel| self.palette[(pixel & 0x3) as usize]).collect()
+    }
+}
+
+impl Default for Display{
+    fn default() -> Self{
+        Display::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::Display;
+
+    #[test]
+    fn sprite_clips_at_edge(){
+        let mut display = Display::new();
+        let collision = display.draw_sprite(60, 0, 8, &[0xFF], 1, false);
+
+        assert!(!collision);
+        assert_eq!(display.pixels[56..64], [0, 0, 0, 0, 1, 1, 1, 1]);
+        assert_eq!(display.pixels[64], 0);
+        assert!(display.draw_sprite(60, 0, 8, &[0x80], 1, false));
+    }
+
+    #[test]
+    fn sprite_wraps(){
+        let mut display = Display::new();
+        display.draw_sprite(62, 31, 8, &[0xC0, 0xE0], 1, true);
+
+        assert_eq!(display.get(62, 31), 1);
+        assert_eq!(display.get(63, 31), 1);
+        assert_eq!(display.get(0, 0), 1);
+        assert_eq!(display.get(1, 0), 0);
+    }
+
+    #[test]
+    fn scrolls(){
+        let mut display = Display::new();
+        display.set_hires(true);
+        display.draw_sprite(0, 0, 16, &[0x8001], 1, false);
+
+        display.scroll_right(4);
+        assert_eq!(display.get(4, 0), 1);
+        assert_eq!(display.get(19, 0), 1);
+
+        display.scroll_left(4);
+        display.scroll_down(2);
+        assert_eq!(display.get(0, 0), 0);
+        assert_eq!(display.get(0, 2), 1);
+        assert_eq!(display.get(15, 2), 1);
+    }
+
+    #[test]
+    fn planes_are_independent(){
+        let mut display = Display::new();
+        display.draw_sprite(0, 0, 8, &[0xC0], 1, false);
+        display.draw_sprite(0, 0, 8, &[0x60], 2, false);
+        assert_eq!(display.pixels[0..3], [1, 3, 2]);
+        assert_eq!(display.to_rgb()[0..3], [0xFFFFFF, 0x555555, 0xAAAAAA]);
+
+        display.plane = 2;
+        display.scroll_right(4);
+        display.clear();
+        assert_eq!(display.pixels[0..3], [1, 1, 0]);
+    }
+}
@@ -0,0 +1,33 @@
+use std::fmt;
+
+/// Everything that can stop a program, each instruction error records where it happened
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmulatorError{
+    UnknownOpcode {pc : u16, opcode : u16},
+    /// CALL with all 16 stack slots in use
+    StackOverflow {pc : u16, opcode : u16},
+    /// RET with nothing on the stack
+    StackUnderflow {pc : u16, opcode : u16},
+    OutOfBoundsMemory {pc : u16, opcode : u16, addr : usize},
+    /// SKP/SKNP with a Vx that is not one of the 16 keys
+    InvalidKey {pc : u16, opcode : u16, key : u8},
+    RomTooLarge {size : usize, max : usize},
+    /// The ROM file could not be read
+    Io(String)
+}
+
+impl fmt::Display for EmulatorError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            EmulatorError::UnknownOpcode {pc, opcode} => write!(f, "Unknown opcode {:04X} at {:#05X}", opcode, pc),
+            EmulatorError::StackOverflow {pc, opcode} => write!(f, "Stack overflow by opcode {:04X} at {:#05X}", opcode, pc),
+            EmulatorError::StackUnderflow {pc, opcode} => write!(f, "Stack underflow by opcode {:04X} at {:#05X}", opcode, pc),
+            EmulatorError::OutOfBoundsMemory {pc, opcode, addr} => write!(f, "Opcode {:04X} at {:#05X} accessed memory out of bounds at {:#X}", opcode, pc, addr),
+            EmulatorError::InvalidKey {pc, opcode, key} => write!(f, "Opcode {:04X} at {:#05X} checked invalid key {:#X}", opcode, pc, key),
+            EmulatorError::RomTooLarge {size, max} => write!(f, "ROM is {} bytes but at most {} fit in memory", size, max),
+            EmulatorError::Io(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl std::error::Error for EmulatorError {}
@@ -0,0 +1,35 @@
+use chip_8::Cpu;
+
+/// Emulator controls outside the CHIP-8 keypad
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hotkey{
+    SaveState,
+    LoadState,
+    Screenshot
+}
+
+/// Somewhere to show the screen and read the keypad from, the run loop works the same whichever is in use
+pub trait Frontend{
+    fn is_open(&self) -> bool;
+
+    /// Pushes the screen out if it changed and picks up new input
+    fn draw(&mut self, processor: &mut Cpu);
+
+    /// True while the rewind key is held
+    fn rewinding(&self) -> bool;
+
+    /// Hotkeys pressed since the last draw
+    fn hotkeys(&mut self) -> Vec<Hotkey>;
+
+    fn update_keys(&mut self, processor: &mut Cpu);
+
+    /// Shows a line of status, such as why the debugger stopped
+    fn report(&mut self, message: &str){
+        println!("{}", message);
+    }
+
+    /// Hands stdin and stdout over to the debugger prompt until resume
+    fn suspend(&mut self){}
+
+    fn resume(&mut self){}
+}
@@ -0,0 +1,43 @@
+//! Core of the SCUF-8 CHIP-8 interpreter, usable without any window.
+//!
+//! ```no_run
+//! let rom = std::fs::read("TestRoms/test_opcode.ch8").unwrap();
+//! let mut processor = chip_8::Cpu::new(&rom).unwrap();
+//!
+//! processor.set_keypad([false; 16]);
+//! processor.run(10).unwrap();
+//! processor.tick_timers();
+//!
+//! let pixels = processor.buffer();
+//! ```
+
+pub mod memory;
+pub mod cpu;
+pub mod opcode;
+pub mod display;
+pub mod quirks;
+pub mod error;
+pub mod debugger;
+pub mod disasm;
+pub mod asm;
+pub mod state;
+pub mod rewind;
+pub mod movie;
+pub mod rng;
+pub mod timing;
+pub mod audio;
+pub mod screenshot;
+pub mod video;
+pub mod testrom;
+pub mod snapshot;
+pub mod machine;
+pub mod trace;
+
+pub use cpu::Cpu;
+pub use memory::Memory;
+pub use display::Display;
+pub use quirks::{Quirks, Preset};
+pub use error::EmulatorError;
+pub use state::StateError;
+pub use debugger::Debugger;
+pub use opcode::{Opcode, OpcodeTypes, Platform};
@@ -0,0 +1,114 @@
+use crate::cpu::Cpu;
+use crate::error::EmulatorError;
+use crate::quirks::Quirks;
+
+/// Sets up a machine in a given state to run single instructions on, for tests
+///
+/// ```
+/// let processor = chip_8::machine::MachineBuilder::new().reg(0x1, 0x20).reg(0x2, 0x30).run(0x8124).unwrap();
+/// assert_eq!((processor.memory.reg[0x1], processor.memory.reg[0xF]), (0x50, 0));
+/// ```
+pub struct MachineBuilder{
+    processor : Cpu
+}
+
+impl MachineBuilder{
+    /// An empty machine with the fonts loaded, the pc at 0x200 and the COSMAC VIP quirks
+    pub fn new() -> MachineBuilder{
+        MachineBuilder {processor : Cpu::new(&[]).expect("an empty ROM always fits")}
+    }
+
+    pub fn reg(mut self, reg: usize, value: u8) -> Self{
+        self.processor.memory.reg[reg] = value;
+        self
+    }
+
+    pub fn regs(mut self, values: &[u8]) -> Self{
+        self.processor.memory.reg[..values.len()].copy_from_slice(values);
+        self
+    }
+
+    pub fn i(mut self, i: u16) -> Self{
+        self.processor.memory.i = i;
+        self
+    }
+
+    pub fn pc(mut self, pc: u16) -> Self{
+        self.processor.memory.pc = pc;
+        self
+    }
+
+    /// Writes `bytes` to memory starting at `addr`
+    pub fn mem(mut self, addr: usize, bytes: &[u8]) -> Self{
+        self.processor.memory.addr_mem[addr..addr + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    /// Pushes return addresses, the last one is on top
+    pub fn stack(mut self, addrs: &[u16]) -> Self{
+        for (slot, &addr) in addrs.iter().enumerate(){
+            self.processor.memory.stack[slot] = Some(addr);
+        }
+        self.processor.memory.sp = addrs.len() as u8;
+        self
+    }
+
+    /// Sets the SCHIP RPL user flags
+    pub fn rpl(mut self, values: &[u8]) -> Self{
+        self.processor.memory.rpl[..values.len()].copy_from_slice(values);
+        self
+    }
+
+    pub fn timers(mut self, delay: u8, sound: u8) -> Self{
+        self.processor.memory.delay = delay;
+        self.processor.memory.sound = sound;
+        self
+    }
+
+    /// Holds these keys down, as newly pressed for LDVxK
+    pub fn keys(mut self, keys: &[u8]) -> Self{
+        self.processor.set_keypad(std::array::from_fn(|key| keys.contains(&(key as u8))));
+        self
+    }
+
+    /// Also grows or shrinks memory to the quirks' platform
+    pub fn quirks(mut self, quirks: Quirks) -> Self{
+        self.processor.quirks = quirks;
+        self.processor.memory.addr_mem.resize(quirks.platform.memory_size(), 0);
+        self
+    }
+
+    pub fn hires(mut self) -> Self{
+        self.processor.curr_buffer.set_hires(true);
+        self
+    }
+
+    /// Sets pixels by index on the given planes
+    pub fn pixels(mut self, indices: &[usize], planes: u8) -> Self{
+        for &index in indices{
+            self.processor.curr_buffer.pixels[index] |= planes;
+        }
+        self
+    }
+
+    pub fn build(self) -> Cpu{
+        self.processor
+    }
+
+    /// Writes `code` at the pc and runs it as one instruction. Four byte instructions take their second word from
+    /// memory after the pc, so set that with `mem` first.
+    pub fn run(self, code: u16) -> Result<Cpu, EmulatorError>{
+        let mut processor = self.processor;
+        let pc = processor.memory.pc as usize;
+        processor.memory.addr_mem[pc..pc + 2].copy_from_slice(&code.to_be_bytes());
+        processor.step()?;
+
+        Ok(processor)
+    }
+}
+
+impl Default for MachineBuilder{
+    fn default() -> Self{
+        MachineBuilder::new()
+    }
+}
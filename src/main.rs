@@ -1,69 +1,541 @@
-mod memory;
-mod cpu;
-mod opcode;
-extern crate minifb;
+mod frontend;
+mod tui;
+mod window;
 extern crate clap;
 
-use std::time::Instant;
+use std::fs;
+use std::path::Path;
+use std::io::{self, BufRead, Write};
 
-use cpu::Cpu;
-use minifb::Key;
-use opcode::OpcodeTypes;
-use clap::Parser;
+use chip_8::{Cpu, Debugger, OpcodeTypes, Preset};
+use chip_8::debugger::{Command, Watchpoint, registers, memory_dump, parse_addr};
+use chip_8::asm::assemble_file;
+use chip_8::disasm::{disassemble, mnemonic};
+use chip_8::rewind::Rewind;
+use chip_8::movie::Movie;
+use chip_8::rng::ByteReplay;
+use chip_8::timing::{Scheduler, Speed};
+use chip_8::audio::{AudioSink, Beeper, WavSink};
+use chip_8::screenshot::{next_free_path, save_png};
+use chip_8::video::{self, VideoSink};
+use chip_8::testrom::{load_manifest, Outcome};
+use chip_8::trace::{self, load_trace};
+use frontend::{Frontend, Hotkey};
+use tui::TerminalFrontend;
+use window::WindowFrontend;
+use clap::{Parser, Subcommand};
+
+/// Ten seconds of frames to rewind through
+const REWIND_FRAMES: usize = 600;
 
 #[derive(Parser)]
 #[command(name = "SCUF-8")]
 #[command(author = "Aman Rao (amanrao032@gmail.com)")]
 #[command(version = "1.0")]
 #[command(about = "Scuffed Chip-8 Emulator", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli{
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// File Path for Chip 8 program
-    path: String
+    #[arg(required = true)]
+    path: Option<String>,
+
+    /// Quirk preset to run the program under: vip, chip48, schip or xochip
+    #[arg(short, long, default_value = "vip")]
+    quirks: Preset,
+
+    /// Start paused in the step debugger
+    #[arg(short, long)]
+    debug: bool,
+
+    /// Pause in the debugger when the pc reaches this hex address, can be repeated
+    #[arg(short, long = "break", value_parser = parse_break)]
+    breakpoints: Vec<u16>,
+
+    /// Pause when a hex address range such as 300-30F is accessed, append :r, :w or :rw to choose how (default :w)
+    #[arg(short, long, value_parser = parse_watch)]
+    watch: Vec<Watchpoint>,
+
+    /// Pause before an instruction of this kind runs, such as DRWVxVyNibble, can be repeated
+    #[arg(long = "break-op")]
+    break_ops: Vec<OpcodeTypes>,
+
+    /// Save state file for F5 (save) and F9 (load), also loaded at start if it exists. Defaults to the ROM path with a .state extension
+    #[arg(short, long)]
+    state: Option<String>,
+
+    /// Record the keypad and seed to this movie file, which replay plays back exactly
+    #[arg(short, long, conflicts_with_all = ["debug", "breakpoints", "watch", "break_ops", "state", "random_file"])]
+    record: Option<String>,
+
+    /// Seed for RNDVxbyte so every run gives the same random numbers, picked at random when left out
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Take RNDVxbyte's random bytes from this file in order, starting over at the end
+    #[arg(long, conflicts_with = "seed")]
+    random_file: Option<String>,
+
+    /// Instructions per 60Hz frame, or vip to run as many as fit in a frame of COSMAC VIP machine cycles
+    #[arg(long, default_value = "11")]
+    speed: Speed,
+
+    /// Run frames back to back instead of 60 per second
+    #[arg(long)]
+    unthrottled: bool,
+
+    /// Draw in the terminal with half block characters instead of opening a window, for machines without a display
+    #[arg(long)]
+    tui: bool,
+
+    /// Write the sound to this WAV file, timed by emulated frames so it matches the run whatever the speed
+    #[arg(long)]
+    record_audio: Option<String>,
+
+    /// Times to scale F12 screenshots up from the native resolution
+    #[arg(long, default_value = "1")]
+    screenshot_scale: usize,
+
+    /// Capture every frame to this animated GIF, or to numbered PNGs in this directory if it doesn't end in .gif
+    #[arg(long)]
+    record_video: Option<String>,
+
+    /// Times to scale recorded video up from the native resolution
+    #[arg(long, default_value = "1")]
+    video_scale: usize,
+
+    /// Don't play sound
+    #[cfg(feature = "audio")]
+    #[arg(long)]
+    mute: bool
+}
+
+#[derive(Subcommand)]
+enum Commands{
+    /// Print a listing of a ROM
+    Disasm{
+        /// File Path for Chip 8 program
+        path: String,
+
+        /// Decode the instructions of this preset's platform, schip and xochip add their extensions
+        #[arg(short, long, default_value = "vip")]
+        quirks: Preset,
+
+        /// Write the listing here instead of to stdout
+        #[arg(short, long)]
+        output: Option<String>
+    },
+    /// Build a ROM from assembly source
+    Asm{
+        /// Source file using the mnemonics disasm prints
+        path: String,
+
+        /// Where to write the ROM, defaults to the source path with a .ch8 extension
+        #[arg(short, long)]
+        output: Option<String>
+    },
+    /// Play a movie made with --record without a window and print the final registers
+    Replay{
+        /// File Path for Chip 8 program
+        path: String,
+
+        movie: String,
+
+        /// Also write the final machine to this save state
+        #[arg(short, long)]
+        state: Option<String>,
+
+        /// Write the movie's sound to this WAV file
+        #[arg(long)]
+        record_audio: Option<String>,
+
+        /// Capture the movie's frames to this GIF or PNG directory, like the main --record-video
+        #[arg(long)]
+        record_video: Option<String>,
+
+        #[arg(long, default_value = "1")]
+        video_scale: usize
+    },
+    /// Run test ROMs without a window and compare their final screens with golden images
+    TestRoms{
+        /// Manifest listing each ROM, how many frames to run and which keys to hold
+        #[arg(default_value = "TestRoms/tests.txt")]
+        manifest: String,
+
+        /// Write the golden images from this run instead of checking them
+        #[arg(long)]
+        update: bool
+    },
+    /// Step a ROM alongside a reference trace from another emulator and show the first instruction where they differ,
+    /// or write this emulator's trace in the same format
+    Trace{
+        /// File Path for Chip 8 program
+        path: String,
+
+        /// Reference trace with a line of hex PC=, OP=, V0= to VF=, I=, SP=, DT= and ST= fields per instruction
+        #[arg(short, long)]
+        against: Option<String>,
+
+        /// Instructions to trace when writing one
+        #[arg(short, long, default_value = "1000")]
+        count: usize,
+
+        /// Write the trace here instead of to stdout
+        #[arg(short, long, conflicts_with = "against")]
+        output: Option<String>,
+
+        #[arg(short, long, default_value = "vip")]
+        quirks: Preset,
+
+        /// Instructions per frame, which decides when the timers tick
+        #[arg(long, default_value = "11")]
+        speed: Speed,
+
+        #[arg(long, default_value = "0")]
+        seed: u64
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
 
-    let path = &cli.path;
-    let mut processor = Cpu::initialize(path);
+    match cli.command{
+        Some(Commands::Disasm {path, quirks, output}) => disasm(&path, quirks, output.as_deref()),
+        Some(Commands::Asm {path, output}) => asm(&path, output),
+        Some(Commands::Replay {path, movie, state, record_audio, record_video, video_scale}) =>
+            replay(&path, &movie, state.as_deref(), record_audio.as_deref(), record_video.as_deref(), video_scale),
+        Some(Commands::TestRoms {manifest, update}) => test_roms(&manifest, update),
+        Some(Commands::Trace {path, against, count, output, quirks, speed, seed}) =>
+            trace(&path, against.as_deref(), count, output.as_deref(), quirks, speed, seed),
+        None => run(cli)
+    }
+}
 
-    let mut last_cycle = Instant::now();
+fn disasm(path: &str, quirks: Preset, output: Option<&str>){
+    let rom = fs::read(path).unwrap_or_else(|err| exit_with(err));
+    let listing: String = disassemble(&rom, quirks.into()).unwrap_or_else(|err| exit_with(err)).iter().map(|line| format!("{}\n", line)).collect();
 
-    while processor.window.is_open() && !processor.window.is_key_down(Key::Escape){
-        processor.fetch();
-        processor.decode();
-        processor.execute();
+    match output{
+        Some(output) => fs::write(output, listing).unwrap_or_else(|err| exit_with(err)),
+        None => print!("{}", listing)
+    }
+}
 
-        if processor.opcode.kind != Some(OpcodeTypes::CALLAddr) && processor.opcode.kind != Some(OpcodeTypes::SNEVxByte) && 
-        processor.opcode.kind != Some(OpcodeTypes::RET) && processor.opcode.kind != Some(OpcodeTypes::JPAddr) && processor.opcode.kind != Some(OpcodeTypes::SEVxVy)
-        && processor.opcode.kind != Some(OpcodeTypes::JPV0Addr) && processor.opcode.kind != Some(OpcodeTypes::SKPVx) && processor.opcode.kind != Some(OpcodeTypes::SKNPVx)
-        && processor.opcode.kind != Some(OpcodeTypes::SEVxByte)
-        {
-            processor.memory.pc += 2;
-        }
+fn asm(path: &str, output: Option<String>){
+    let rom = assemble_file(Path::new(path)).unwrap_or_else(|err| exit_with(err));
+    let output = output.unwrap_or_else(|| Path::new(path).with_extension("ch8").display().to_string());
+
+    fs::write(output, rom).unwrap_or_else(|err| exit_with(err));
+}
+
+fn replay(path: &str, movie: &str, state: Option<&str>, record_audio: Option<&str>, record_video: Option<&str>, video_scale: usize){
+    let rom = fs::read(path).unwrap_or_else(|err| exit_with(err));
+    let movie = Movie::load(Path::new(movie)).unwrap_or_else(|err| exit_with(err));
+    let mut processor = movie.start(&rom).unwrap_or_else(|err| exit_with(err));
+    let mut beeper = Beeper::new();
+    let mut sinks = wav_sink(record_audio);
+    let mut video = video_sink(record_video, video_scale);
 
-        if processor.opcode.kind == Some(OpcodeTypes::JPAddr){
-            let addr = processor.opcode.code & 0xFFF;
-            if processor.memory.addr_mem[addr as usize] == processor.opcode.code.to_be_bytes()[0] && processor.memory.addr_mem[(addr + 1) as usize] == processor.opcode.code.to_be_bytes()[1]{
-                processor.reset();
+    let result = movie.replay(&mut processor, |processor| {
+        play(&mut beeper, &mut sinks, processor);
+        film(&mut video, processor);
+    });
+    finish_audio(&mut sinks);
+    finish_video(&mut video);
+    result.unwrap_or_else(|err| exit_with(err));
+    println!("{} frames, {} instructions", movie.frames.len(), processor.cycles);
+    println!("{}", registers(&processor));
+
+    if let Some(state) = state{
+        processor.save_state_file(Path::new(state)).unwrap_or_else(|err| exit_with(err));
+    }
+}
+
+fn test_roms(manifest: &str, update: bool){
+    let tests = load_manifest(Path::new(manifest)).unwrap_or_else(|err| exit_with(err));
+    let mut failed = 0;
+
+    for test in tests.iter(){
+        match test.check(update){
+            Outcome::Pass => println!("PASS {}", test.name),
+            Outcome::Updated => println!("UPDATED {}", test.name),
+            Outcome::Fail(reason) => {
+                println!("FAIL {}: {}", test.name, reason);
+                failed += 1;
             }
         }
+    }
+
+    println!("{} of {} passed", tests.len() - failed, tests.len());
+    if failed > 0{
+        std::process::exit(1);
+    }
+}
+
+fn trace(path: &str, against: Option<&str>, count: usize, output: Option<&str>, quirks: Preset, speed: Speed, seed: u64){
+    let rom = fs::read(path).unwrap_or_else(|err| exit_with(err));
+    let mut processor = Cpu::with_quirks(&rom, quirks.into()).unwrap_or_else(|err| exit_with(err));
+    processor.set_seed(seed);
+
+    let Some(against) = against else {
+        let lines: String = trace::record(&mut processor, count, speed).unwrap_or_else(|err| exit_with(err)).iter().map(|entry| format!("{}\n", entry)).collect();
+        match output{
+            Some(output) => fs::write(output, lines).unwrap_or_else(|err| exit_with(err)),
+            None => print!("{}", lines)
+        }
+        return;
+    };
+
+    let reference = load_trace(Path::new(against)).unwrap_or_else(|err| exit_with(err));
+    match trace::compare(&mut processor, &reference, speed){
+        Ok(matched) => println!("All {} instructions match", matched),
+        Err(diff) => {
+            print!("{}", diff);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run(cli: Cli){
+    let path = cli.path.as_deref().unwrap();
+    let rom = fs::read(path).unwrap_or_else(|err| exit_with(err));
+    let mut processor = Cpu::with_quirks(&rom, cli.quirks.into()).unwrap_or_else(|err| exit_with(err));
+    if let Some(seed) = cli.seed{
+        processor.set_seed(seed);
+    }
+    if let Some(random_file) = cli.random_file.as_deref(){
+        let bytes = fs::read(random_file).unwrap_or_else(|err| exit_with(err));
+        processor.rng = Box::new(ByteReplay::new(bytes).unwrap_or_else(|| exit_with(format!("{} is empty", random_file))));
+    }
+
+    let state_path = cli.state.clone().unwrap_or_else(|| Path::new(path).with_extension("state").display().to_string());
+    if cli.state.is_some() && Path::new(&state_path).exists(){
+        processor.load_state_file(Path::new(&state_path)).unwrap_or_else(|err| exit_with(err));
+    }
+    let mut beeper = Beeper::new();
+    let mut sinks: Vec<Box<dyn AudioSink>> = device_sink(&cli).into_iter().chain(wav_sink(cli.record_audio.as_deref())).collect();
+    let mut video = video_sink(cli.record_video.as_deref(), cli.video_scale);
+    let mut frontend: Box<dyn Frontend> = if cli.tui{
+        Box::new(TerminalFrontend::new().unwrap_or_else(|err| exit_with(err)))
+    }
+    else{
+        Box::new(WindowFrontend::new())
+    };
+
+    let mut debugger = if cli.debug || !cli.breakpoints.is_empty() || !cli.watch.is_empty() || !cli.break_ops.is_empty(){
+        let mut debugger = Debugger::new();
+        debugger.breakpoints.extend(cli.breakpoints.iter());
+        debugger.watchpoints = cli.watch;
+        debugger.opcode_breaks = cli.break_ops;
+        debugger.paused = cli.debug;
+        Some(debugger)
+    }
+    else{
+        None
+    };
+
+    let mut movie = cli.record.as_ref().map(|_| Movie::new(&rom, &processor));
+    let mut rewind = Rewind::new(REWIND_FRAMES);
+    let mut scheduler = Scheduler::new(cli.speed, !cli.unthrottled);
+
+    while frontend.is_open() && !processor.exited{
+        if let Some(debugger) = debugger.as_mut().filter(|debugger| debugger.paused){
+            frontend.draw(&mut processor);
+            frontend.suspend();
+            debug_prompt(debugger, &mut processor);
+            frontend.resume();
+            continue;
+        }
 
-        let now = Instant::now();
-        let time_elapsed = now.duration_since(last_cycle);
+        if movie.is_none() && frontend.rewinding(){
+            rewind.rewind(&mut processor);
+        }
+        else{
+            let result = scheduler.run_frame_with(&mut processor, |processor| match debugger.as_mut(){
+                Some(debugger) => debugger.run_step(processor).map(|reason| {
+                    if let Some(reason) = &reason{
+                        frontend.report(&format!("{:?}", reason));
+                    }
+                    reason.is_none()
+                }),
+                None => processor.step().map(|_| {
+                    restart_on_halt(processor);
+                    true
+                })
+            });
 
-        if time_elapsed.as_micros() >= 16670{
-            if processor.memory.sound > 0{
-                processor.memory.sound -= 1;
+            match result{
+                Ok(true) => {
+                    rewind.push(&processor);
+                    play(&mut beeper, &mut sinks, &processor);
+                    film(&mut video, &processor);
+                },
+                Ok(false) => {},
+                // The debugger has paused in front of the failing instruction, so it can be looked into at the prompt
+                Err(err) if debugger.is_some() => frontend.report(&err.to_string()),
+                Err(err) => {
+                    // Keep the instructions up to the error so the replay fails the same way
+                    if let Some(movie) = movie.as_mut(){
+                        movie.record(&processor);
+                    }
+                    save_movie(movie.as_ref(), cli.record.as_deref());
+                    finish_audio(&mut sinks);
+                    finish_video(&mut video);
+                    exit_with(err);
+                }
             }
+        }
+
+        frontend.draw(&mut processor);
+        frontend.update_keys(&mut processor);
+        if let Some(movie) = movie.as_mut(){
+            movie.record(&processor);
+        }
 
-            if processor.memory.delay > 0{
-                processor.memory.delay -= 1;
+        for hotkey in frontend.hotkeys(){
+            let result = match hotkey{
+                Hotkey::SaveState => processor.save_state_file(Path::new(&state_path)),
+                Hotkey::LoadState if movie.is_some() => {
+                    frontend.report("Loading states is disabled while recording a movie");
+                    continue;
+                },
+                Hotkey::LoadState => processor.load_state_file(Path::new(&state_path)),
+                Hotkey::Screenshot => {
+                    let shot = next_free_path(Path::new(path));
+                    match save_png(&processor.curr_buffer, cli.screenshot_scale, &shot){
+                        Ok(()) => frontend.report(&format!("{:?} {}", hotkey, shot.display())),
+                        Err(err) => frontend.report(&err.to_string())
+                    }
+                    continue;
+                }
+            };
+            match result{
+                Ok(()) => frontend.report(&format!("{:?} {}", hotkey, state_path)),
+                Err(err) => frontend.report(&err.to_string())
             }
+        }
+
+        scheduler.wait();
+    }
+
+    finish_audio(&mut sinks);
+    finish_video(&mut video);
+    save_movie(movie.as_ref(), cli.record.as_deref());
+}
+
+#[cfg(feature = "audio")]
+fn device_sink(cli: &Cli) -> Option<Box<dyn AudioSink>>{
+    if cli.mute{
+        return None;
+    }
 
-            last_cycle = now;
+    match chip_8::audio::DeviceSink::open(){
+        Ok(sink) => Some(Box::new(sink)),
+        Err(err) => {
+            eprintln!("No sound: {}", err);
+            None
         }
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+fn device_sink(_cli: &Cli) -> Option<Box<dyn AudioSink>>{
+    None
+}
+
+fn wav_sink(path: Option<&str>) -> Vec<Box<dyn AudioSink>>{
+    path.map(|path| Box::new(WavSink::create(Path::new(path)).unwrap_or_else(|err| exit_with(err))) as Box<dyn AudioSink>).into_iter().collect()
+}
+
+fn finish_audio(sinks: &mut [Box<dyn AudioSink>]){
+    for sink in sinks.iter_mut(){
+        sink.finish().unwrap_or_else(|err| exit_with(err));
+    }
+}
+
+fn video_sink(path: Option<&str>, scale: usize) -> Option<Box<dyn VideoSink>>{
+    path.map(|path| video::create(Path::new(path), scale).unwrap_or_else(|err| exit_with(err)))
+}
+
+fn film(video: &mut Option<Box<dyn VideoSink>>, processor: &Cpu){
+    if let Some(video) = video.as_mut(){
+        video.frame(&processor.curr_buffer).unwrap_or_else(|err| exit_with(err));
+    }
+}
+
+fn finish_video(video: &mut Option<Box<dyn VideoSink>>){
+    if let Some(video) = video.as_mut(){
+        video.finish().unwrap_or_else(|err| exit_with(err));
+    }
+}
+
+fn play(beeper: &mut Beeper, sinks: &mut [Box<dyn AudioSink>], processor: &Cpu){
+    if sinks.is_empty(){
+        return;
+    }
+
+    let samples = beeper.frame(processor);
+    for sink in sinks.iter_mut(){
+        sink.write(&samples).unwrap_or_else(|err| exit_with(err));
+    }
+}
 
-        processor.key = processor.window.get_keys_pressed(minifb::KeyRepeat::No)
+fn save_movie(movie: Option<&Movie>, path: Option<&str>){
+    if let (Some(movie), Some(path)) = (movie, path){
+        movie.save(Path::new(path)).unwrap_or_else(|err| exit_with(err));
     }
 }
+
+/// Programs end by jumping to themselves, so start them over
+fn restart_on_halt(processor: &mut Cpu){
+    if processor.halted(){
+        processor.reset();
+    }
+}
+
+fn debug_prompt(debugger: &mut Debugger, processor: &mut Cpu){
+    println!("{}", registers(processor));
+    let pc = processor.memory.pc as usize;
+    let size = processor.memory.addr_mem.len();
+    let word = |addr: usize| u16::from_be_bytes([processor.memory.addr_mem[addr % size], processor.memory.addr_mem[(addr + 1) % size]]);
+    println!("{:#05X}: {}", pc, mnemonic(word(pc), word(pc + 2)).unwrap_or_else(|err| err));
+    print!("(scuf) ");
+    io::stdout().flush().unwrap();
+
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line).unwrap() == 0{
+        std::process::exit(0);
+    }
+
+    match Command::parse(&line){
+        Ok(Command::Quit) => std::process::exit(0),
+        Ok(Command::Registers) => {},
+        Ok(Command::Memory(addr, len)) => println!("{}", memory_dump(processor, addr, len)),
+        Ok(command) => match debugger.apply(&command, processor){
+            Ok(Some(reason)) => println!("{:?}", reason),
+            Ok(None) => {},
+            Err(err) => println!("{}", err)
+        },
+        Err(err) => println!("{}", err)
+    }
+}
+
+fn parse_break(arg: &str) -> Result<u16, String>{
+    parse_addr(Some(arg))
+}
+
+fn parse_watch(arg: &str) -> Result<Watchpoint, String>{
+    let (range, mode) = match arg.split_once(':'){
+        Some((range, mode)) => (range, Some(mode)),
+        None => (arg, None)
+    };
+    Watchpoint::parse(Some(range), mode)
+}
+
+fn exit_with(err: impl std::fmt::Display) -> !{
+    tui::restore();
+    eprintln!("{}", err);
+    std::process::exit(1)
+}
@@ -1,10 +1,31 @@
+/// A data read or write made by an instruction, instruction fetches are not included
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryAccess{
+    pub addr : usize,
+    pub write : bool
+}
+
+/// XO-CHIP address space, the most any platform has
+pub const MEMORY_SIZE: usize = 0x10000;
+
+/// CHIP-8 and SCHIP address space
+pub const CHIP8_MEMORY_SIZE: usize = 0x1000;
+
 pub struct Memory{
-    pub addr_mem : [u8; 4096],
+    pub addr_mem : Vec<u8>,
     pub reg : [u8; 16],
     pub i : u16,
     pub pc : u16,
     pub stack : [Option<u16>; 16],
     pub sp : u8,
     pub delay : u8,
-    pub sound : u8
+    pub sound : u8,
+    /// SCHIP RPL user flags, saved and restored by FX75/FX85
+    pub rpl : [u8; 16],
+    /// XO-CHIP audio pattern buffer, loaded by F002
+    pub pattern : [u8; 16],
+    /// Set once F002 has run, until then the sound timer plays a plain beep
+    pub pattern_loaded : bool,
+    /// XO-CHIP playback pitch set by FX3A, 64 plays the pattern at 4000 bits per second
+    pub pitch : u8
 }
\ No newline at end of file
@@ -0,0 +1,171 @@
+use std::fs;
+use std::path::Path;
+use crate::cpu::Cpu;
+use crate::error::EmulatorError;
+use crate::quirks::Quirks;
+
+const HEADER: &str = "SCUF-8 movie 1";
+
+/// What happened between two 60Hz ticks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame{
+    /// Instructions executed before the tick
+    pub steps : u32,
+    /// Keys held at the tick, bit n is key n
+    pub keys : u16
+}
+
+/// Everything needed to play a run back exactly: the seed, the quirks, and per frame the instruction count and keypad
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie{
+    pub seed : u64,
+    pub quirks : Quirks,
+    /// FNV-1a hash of the ROM the movie was recorded with
+    pub rom_hash : u64,
+    pub frames : Vec<Frame>,
+    last_cycles : u64
+}
+
+pub fn rom_hash(rom: &[u8]) -> u64{
+    rom.iter().fold(0xCBF29CE484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001B3))
+}
+
+impl Movie{
+    /// Starts recording a freshly created machine
+    pub fn new(rom: &[u8], processor: &Cpu) -> Movie{
+        Movie {seed : processor.seed, quirks : processor.quirks, rom_hash : rom_hash(rom), frames : Vec::new(), last_cycles : processor.cycles}
+    }
+
+    /// Records a frame, called after the timers tick and the keypad is updated
+    pub fn record(&mut self, processor: &Cpu){
+        let keys = processor.keypad.iter().enumerate().fold(0, |keys, (key, &down)| keys | ((down as u16) << key));
+
+        self.frames.push(Frame {steps : (processor.cycles - self.last_cycles) as u32, keys});
+        self.last_cycles = processor.cycles;
+    }
+
+    /// Creates the machine the movie was recorded on, failing if `rom` is not the one it was recorded with
+    pub fn start(&self, rom: &[u8]) -> Result<Cpu, String>{
+        if rom_hash(rom) != self.rom_hash{
+            return Err(format!("Movie was recorded with a ROM hashing to {:016X}, not {:016X}", self.rom_hash, rom_hash(rom)));
+        }
+
+        let mut processor = Cpu::with_quirks(rom, self.quirks).map_err(|err| err.to_string())?;
+        processor.set_seed(self.seed);
+
+        Ok(processor)
+    }
+
+    /// Plays every frame on a machine from `start`, calling `on_frame` after each. Programs that jump to themselves are
+    /// restarted like the window frontend does.
+    pub fn replay(&self, processor: &mut Cpu, mut on_frame: impl FnMut(&Cpu)) -> Result<(), EmulatorError>{
+        for frame in self.frames.iter(){
+            let end = processor.cycles + frame.steps as u64;
+            while processor.cycles < end && !processor.vblank_wait && !processor.exited{
+                processor.step()?;
+                if processor.halted(){
+                    processor.reset();
+                }
+            }
+
+            processor.tick_timers();
+            processor.set_keypad(std::array::from_fn(|key| frame.keys & (1 << key) != 0));
+            on_frame(processor);
+        }
+
+        Ok(())
+    }
+
+    /// Writes a text file with a header, then one `steps keys` line per frame
+    pub fn to_text(&self) -> String{
+        let mut text = format!("{}\nseed {}\nquirks {}\nrom {:016X}\n", HEADER, self.seed, quirk_flags(&self.quirks), self.rom_hash);
+        for frame in self.frames.iter(){
+            text += &format!("{} {:04X}\n", frame.steps, frame.keys);
+        }
+
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String>{
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim()) != Some(HEADER){
+            return Err(format!("Not a movie, expected the first line to be {}", HEADER));
+        }
+
+        let mut field = |name: &str| -> Result<String, String>{
+            lines.next().and_then(|(_, line)| line.strip_prefix(name)).map(|value| value.trim().to_string()).ok_or(format!("Missing {} line", name))
+        };
+        let seed = field("seed")?.parse().map_err(|_| "Bad seed".to_string())?;
+        let quirks = parse_quirk_flags(&field("quirks")?)?;
+        let rom_hash = u64::from_str_radix(&field("rom")?, 16).map_err(|_| "Bad ROM hash".to_string())?;
+
+        let frames = lines.filter(|(_, line)| !line.trim().is_empty()).map(|(index, line)| {
+            let bad = || format!("Bad frame on line {}", index + 1);
+            let (steps, keys) = line.trim().split_once(' ').ok_or_else(bad)?;
+            Ok(Frame {steps : steps.parse().map_err(|_| bad())?, keys : u16::from_str_radix(keys, 16).map_err(|_| bad())?})
+        }).collect::<Result<Vec<Frame>, String>>()?;
+
+        Ok(Movie {seed, quirks, rom_hash, frames, last_cycles : 0})
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String>{
+        fs::write(path, self.to_text()).map_err(|err| format!("Cannot write {}: {}", path.display(), err))
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, String>{
+        Movie::parse(&fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path.display(), err))?)
+    }
+}
+
+/// The quirks in field order as a string of 0s and 1s, then the platform
+fn quirk_flags(quirks: &Quirks) -> String{
+    let flags: String = [quirks.shift, quirks.load_store, quirks.jump, quirks.vf_reset, quirks.wrap, quirks.display_wait].iter().map(|&on| if on {'1'} else {'0'}).collect();
+    format!("{} {}", flags, quirks.platform)
+}
+
+fn parse_quirk_flags(field: &str) -> Result<Quirks, String>{
+    let (flags, platform) = field.split_once(' ').ok_or("Expected quirk flags and a platform")?;
+    let flags: Vec<bool> = flags.chars().map(|flag| flag == '1').collect();
+    if flags.len() != 6{
+        return Err(format!("Expected 6 quirk flags, got {}", flags.len()));
+    }
+
+    Ok(Quirks {shift : flags[0], load_store : flags[1], jump : flags[2], vf_reset : flags[3], wrap : flags[4], display_wait : flags[5], platform : platform.trim().parse()?})
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::Movie;
+    use crate::cpu::Cpu;
+    use crate::opcode::Platform;
+
+    // LD V0, K ; RND V1, 0xFF ; LD I, 0x300 ; LD [I], V1 ; DRW V1, V0, 1 ; JP 0x200
+    const ROM: [u8; 12] = [0xF0, 0x0A, 0xC1, 0xFF, 0xA3, 0x00, 0xF1, 0x55, 0xD1, 0x01, 0x12, 0x00];
+
+    #[test]
+    fn replays_identically(){
+        let mut processor = Cpu::new(&ROM).unwrap();
+        let mut movie = Movie::new(&ROM, &processor);
+
+        for frame in 0..40{
+            let steps = if frame % 2 == 0 {7} else {3};
+            for _ in 0..steps{
+                processor.step().unwrap();
+            }
+            processor.tick_timers();
+            processor.set_keypad(std::array::from_fn(|key| key == frame % 16 && frame % 3 == 0));
+            movie.record(&processor);
+        }
+
+        let movie = Movie::parse(&movie.to_text()).unwrap();
+        let mut replayed = movie.start(&ROM).unwrap();
+        movie.replay(&mut replayed, |_| {}).unwrap();
+
+        assert_eq!(replayed.save_state(), processor.save_state());
+        assert_eq!(replayed.cycles, processor.cycles);
+        assert!(movie.start(&ROM[..10]).is_err());
+        assert_eq!(movie.quirks.platform, Platform::Chip8);
+        assert!(Movie::parse("SCUF-8 movie 1\nseed 1\nquirks 000101\nrom 0\n").is_err());
+    }
+}
@@ -1,4 +1,9 @@
-#[derive(PartialEq, Debug)]
+use std::fmt;
+use std::str::FromStr;
+use crate::memory::{CHIP8_MEMORY_SIZE, MEMORY_SIZE};
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum OpcodeTypes{
     CLS,
     RET,
@@ -33,7 +38,101 @@ pub enum OpcodeTypes{
     LDFVx,
     LDBVx,
     LDIVx,
-    LDVxI
+    LDVxI,
+    SCDNibble,
+    SCR,
+    SCL,
+    EXIT,
+    LOW,
+    HIGH,
+    LDHFVx,
+    LDRVx,
+    LDVxR,
+    SCUNibble,
+    LDIVxVy,
+    LDVxVyI,
+    LDILong,
+    PLANENibble,
+    AUDIO,
+    LDPITCHVx
+}
+
+/// Instruction sets in the order they extend each other
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform{
+    Chip8,
+    Schip,
+    XoChip
+}
+
+impl Platform{
+    /// Bytes of memory, XO-CHIP extends the address space to 64 KiB
+    pub fn memory_size(&self) -> usize{
+        match self{
+            Platform::Chip8 | Platform::Schip => CHIP8_MEMORY_SIZE,
+            Platform::XoChip => MEMORY_SIZE
+        }
+    }
+}
+
+impl fmt::Display for Platform{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            Platform::Chip8 => write!(f, "chip8"),
+            Platform::Schip => write!(f, "schip"),
+            Platform::XoChip => write!(f, "xochip")
+        }
+    }
+}
+
+impl FromStr for Platform{
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err>{
+        match name.to_ascii_lowercase().as_str(){
+            "chip8" | "chip-8" => Ok(Platform::Chip8),
+            "schip" | "superchip" => Ok(Platform::Schip),
+            "xochip" | "xo-chip" => Ok(Platform::XoChip),
+            _ => Err(format!("Unknown platform {}, expected chip8, schip or xochip", name))
+        }
+    }
+}
+
+impl OpcodeTypes{
+    pub const ALL: [OpcodeTypes; 50] = [
+        OpcodeTypes::CLS, OpcodeTypes::RET, OpcodeTypes::JPAddr, OpcodeTypes::CALLAddr, OpcodeTypes::SEVxByte,
+        OpcodeTypes::SNEVxByte, OpcodeTypes::SEVxVy, OpcodeTypes::LDVxbyte, OpcodeTypes::ADDVxbyte,
+        OpcodeTypes::LDVxVy, OpcodeTypes::ORVxVy, OpcodeTypes::ANDVxVy, OpcodeTypes::XORVxVy, OpcodeTypes::ADDVxVy,
+        OpcodeTypes::SUBVxVy, OpcodeTypes::SHRVxVy, OpcodeTypes::SUBNVxVy, OpcodeTypes::SHLVxVy, OpcodeTypes::SNEVxVy,
+        OpcodeTypes::LDIAddr, OpcodeTypes::JPV0Addr, OpcodeTypes::RNDVxbyte, OpcodeTypes::DRWVxVyNibble,
+        OpcodeTypes::SKPVx, OpcodeTypes::SKNPVx, OpcodeTypes::LDVxDT, OpcodeTypes::LDVxK, OpcodeTypes::LDDTVx,
+        OpcodeTypes::LDSTVx, OpcodeTypes::ADDIVx, OpcodeTypes::LDFVx, OpcodeTypes::LDBVx, OpcodeTypes::LDIVx,
+        OpcodeTypes::LDVxI, OpcodeTypes::SCDNibble, OpcodeTypes::SCR, OpcodeTypes::SCL, OpcodeTypes::EXIT,
+        OpcodeTypes::LOW, OpcodeTypes::HIGH, OpcodeTypes::LDHFVx, OpcodeTypes::LDRVx, OpcodeTypes::LDVxR,
+        OpcodeTypes::SCUNibble, OpcodeTypes::LDIVxVy, OpcodeTypes::LDVxVyI, OpcodeTypes::LDILong,
+        OpcodeTypes::PLANENibble, OpcodeTypes::AUDIO, OpcodeTypes::LDPITCHVx
+    ];
+
+    /// The first instruction set that has this instruction
+    pub fn platform(&self) -> Platform{
+        match self{
+            OpcodeTypes::SCDNibble | OpcodeTypes::SCR | OpcodeTypes::SCL | OpcodeTypes::EXIT | OpcodeTypes::LOW |
+            OpcodeTypes::HIGH | OpcodeTypes::LDHFVx | OpcodeTypes::LDRVx | OpcodeTypes::LDVxR => Platform::Schip,
+            OpcodeTypes::SCUNibble | OpcodeTypes::LDIVxVy | OpcodeTypes::LDVxVyI | OpcodeTypes::LDILong |
+            OpcodeTypes::PLANENibble | OpcodeTypes::AUDIO | OpcodeTypes::LDPITCHVx => Platform::XoChip,
+            _ => Platform::Chip8
+        }
+    }
+}
+
+impl FromStr for OpcodeTypes{
+    type Err = String;
+
+    /// Parses a variant name such as DRWVxVyNibble, ignoring case
+    fn from_str(name: &str) -> Result<Self, Self::Err>{
+        OpcodeTypes::ALL.iter().find(|kind| format!("{:?}", kind).eq_ignore_ascii_case(name)).copied()
+            .ok_or(format!("Unknown opcode kind {}", name))
+    }
 }
 
 pub struct Opcode{
@@ -51,6 +150,34 @@ impl Opcode {
             Ok(OpcodeTypes::RET)
         }
 
+        else if (opcode & 0xFFF0) == 0x00C0 {
+            Ok(OpcodeTypes::SCDNibble)
+        }
+
+        else if (opcode & 0xFFF0) == 0x00D0 {
+            Ok(OpcodeTypes::SCUNibble)
+        }
+
+        else if opcode == 0x00FB {
+            Ok(OpcodeTypes::SCR)
+        }
+
+        else if opcode == 0x00FC {
+            Ok(OpcodeTypes::SCL)
+        }
+
+        else if opcode == 0x00FD {
+            Ok(OpcodeTypes::EXIT)
+        }
+
+        else if opcode == 0x00FE {
+            Ok(OpcodeTypes::LOW)
+        }
+
+        else if opcode == 0x00FF {
+            Ok(OpcodeTypes::HIGH)
+        }
+
         else if (opcode & 0xF000) == 0x1000{
             Ok(OpcodeTypes::JPAddr)
         }
@@ -68,7 +195,21 @@ impl Opcode {
         }
 
         else if (opcode & 0xF000) == 0x5000{
-            Ok(OpcodeTypes::SEVxVy)
+            if (opcode & 0x000F) == 0x0000{
+                Ok(OpcodeTypes::SEVxVy)
+            }
+
+            else if (opcode & 0x000F) == 0x0002{
+                Ok(OpcodeTypes::LDIVxVy)
+            }
+
+            else if (opcode & 0x000F) == 0x0003{
+                Ok(OpcodeTypes::LDVxVyI)
+            }
+
+            else{
+                Err("Incorrect Opcode".to_string())
+            }
         }
 
         else if (opcode & 0xF000) == 0x6000{
@@ -156,7 +297,19 @@ impl Opcode {
         }
 
         else if (opcode & 0xF000) == 0xF000{
-            if (opcode & 0x00FF) == 0x0007{
+            if opcode == 0xF000{
+                Ok(OpcodeTypes::LDILong)
+            }
+
+            else if (opcode & 0xFCFF) == 0xF001{
+                Ok(OpcodeTypes::PLANENibble)
+            }
+
+            else if opcode == 0xF002{
+                Ok(OpcodeTypes::AUDIO)
+            }
+
+            else if (opcode & 0x00FF) == 0x0007{
                 Ok(OpcodeTypes::LDVxDT)
             }
 
@@ -180,10 +333,18 @@ impl Opcode {
                 Ok(OpcodeTypes::LDFVx)
             }
 
+            else if (opcode & 0x00FF) == 0x0030{
+                Ok(OpcodeTypes::LDHFVx)
+            }
+
             else if (opcode & 0x00FF) == 0x0033{
                 Ok(OpcodeTypes::LDBVx)
             }
 
+            else if (opcode & 0x00FF) == 0x003A{
+                Ok(OpcodeTypes::LDPITCHVx)
+            }
+
             else if (opcode & 0x00FF) == 0x0055{
                 Ok(OpcodeTypes::LDIVx)
             }
@@ -192,6 +353,14 @@ impl Opcode {
                 Ok(OpcodeTypes::LDVxI)
             }
 
+            else if (opcode & 0x00FF) == 0x0075{
+                Ok(OpcodeTypes::LDRVx)
+            }
+
+            else if (opcode & 0x00FF) == 0x0085{
+                Ok(OpcodeTypes::LDVxR)
+            }
+
             else{
                 Err("Incorrect Opcode".to_string())
             }
@@ -212,4 +381,29 @@ mod tests{
     fn eight_xy0(){
         assert_eq!(OpcodeTypes::LDVxVy, Opcode::find_kind(0x8120).unwrap())
     }
+
+    #[test]
+    fn kind_from_name(){
+        assert_eq!("drwvxvynibble".parse::<OpcodeTypes>(), Ok(OpcodeTypes::DRWVxVyNibble));
+        assert!("LDQ".parse::<OpcodeTypes>().is_err());
+    }
+
+    #[test]
+    fn schip(){
+        assert_eq!(OpcodeTypes::SCDNibble, Opcode::find_kind(0x00C4).unwrap());
+        assert_eq!(OpcodeTypes::HIGH, Opcode::find_kind(0x00FF).unwrap());
+        assert_eq!(OpcodeTypes::LDHFVx, Opcode::find_kind(0xF330).unwrap());
+        assert_eq!(OpcodeTypes::LDVxR, Opcode::find_kind(0xF785).unwrap());
+        assert!(Opcode::find_kind(0x00FA).is_err());
+    }
+
+    #[test]
+    fn xo_chip(){
+        assert_eq!(OpcodeTypes::LDILong, Opcode::find_kind(0xF000).unwrap());
+        assert_eq!(OpcodeTypes::PLANENibble, Opcode::find_kind(0xF301).unwrap());
+        assert_eq!(OpcodeTypes::LDIVxVy, Opcode::find_kind(0x5122).unwrap());
+        assert_eq!(OpcodeTypes::SEVxVy, Opcode::find_kind(0x5120).unwrap());
+        assert!(Opcode::find_kind(0x5121).is_err());
+        assert!(Opcode::find_kind(0xF401).is_err());
+    }
 }
\ No newline at end of file
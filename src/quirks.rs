@@ -0,0 +1,97 @@
+use std::str::FromStr;
+use crate::opcode::Platform;
+
+/// Instructions that behave differently depending on which interpreter a ROM was written for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks{
+    /// 8XY6/8XYE shift Vx in place instead of storing Vy shifted into Vx
+    pub shift : bool,
+    /// FX55/FX65 leave I unchanged instead of incrementing it past the last register
+    pub load_store : bool,
+    /// BNNN jumps to XNN + Vx instead of NNN + V0
+    pub jump : bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset : bool,
+    /// Sprites wrap around the screen edges instead of being clipped
+    pub wrap : bool,
+    /// DXYN waits for the next 60Hz tick before execution carries on
+    pub display_wait : bool,
+    /// Instructions that exist, which also decides how much memory there is
+    pub platform : Platform
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Preset{
+    CosmacVip,
+    Chip48,
+    Schip,
+    XoChip
+}
+
+impl Quirks{
+    pub fn cosmac_vip() -> Quirks{
+        Quirks {shift : false, load_store : false, jump : false, vf_reset : true, wrap : false, display_wait : true, platform : Platform::Chip8}
+    }
+
+    pub fn chip48() -> Quirks{
+        Quirks {shift : true, load_store : false, jump : true, vf_reset : false, wrap : false, display_wait : false, platform : Platform::Chip8}
+    }
+
+    pub fn schip() -> Quirks{
+        Quirks {shift : true, load_store : true, jump : true, vf_reset : false, wrap : false, display_wait : false, platform : Platform::Schip}
+    }
+
+    pub fn xo_chip() -> Quirks{
+        Quirks {shift : false, load_store : false, jump : false, vf_reset : false, wrap : true, display_wait : false, platform : Platform::XoChip}
+    }
+}
+
+impl Default for Quirks{
+    fn default() -> Self{
+        Quirks::cosmac_vip()
+    }
+}
+
+impl From<Preset> for Quirks{
+    fn from(preset: Preset) -> Self{
+        match preset{
+            Preset::CosmacVip => Quirks::cosmac_vip(),
+            Preset::Chip48 => Quirks::chip48(),
+            Preset::Schip => Quirks::schip(),
+            Preset::XoChip => Quirks::xo_chip()
+        }
+    }
+}
+
+impl From<Preset> for Platform{
+    fn from(preset: Preset) -> Self{
+        Quirks::from(preset).platform
+    }
+}
+
+impl FromStr for Preset{
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err>{
+        match name.to_ascii_lowercase().as_str(){
+            "vip" | "cosmac-vip" => Ok(Preset::CosmacVip),
+            "chip48" | "chip-48" => Ok(Preset::Chip48),
+            "schip" | "superchip" => Ok(Preset::Schip),
+            "xochip" | "xo-chip" => Ok(Preset::XoChip),
+            _ => Err(format!("Unknown quirk preset {}, expected vip, chip48, schip or xochip", name))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{Preset, Quirks};
+
+    #[test]
+    fn presets_parse(){
+        assert_eq!(Quirks::from("xo-chip".parse::<Preset>().unwrap()), Quirks::xo_chip());
+        assert_eq!("SCHIP".parse::<Preset>().unwrap(), Preset::Schip);
+        assert!("chip-9".parse::<Preset>().is_err());
+    }
+}
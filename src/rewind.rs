@@ -0,0 +1,119 @@
+use std::collections::VecDeque;
+use crate::cpu::Cpu;
+
+/// Bytes that turn one save state into another, as runs of replacement bytes
+struct Delta{
+    len : usize,
+    runs : Vec<(usize, Vec<u8>)>
+}
+
+impl Delta{
+    /// Finds the changes that turn `from` into `to`
+    fn between(from: &[u8], to: &[u8]) -> Delta{
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+
+        for (index, &byte) in to.iter().enumerate(){
+            if from.get(index) == Some(&byte){
+                continue;
+            }
+            match runs.last_mut(){
+                Some((start, bytes)) if *start + bytes.len() == index => bytes.push(byte),
+                _ => runs.push((index, vec![byte]))
+            }
+        }
+
+        Delta {len : to.len(), runs}
+    }
+
+    fn apply(&self, state: &mut Vec<u8>){
+        state.resize(self.len, 0);
+        for (start, bytes) in self.runs.iter(){
+            state[*start..*start + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+}
+
+/// Ring buffer of past machine states. Only the newest is kept whole, each older one is stored as the
+/// changes that turn its successor back into it, which is a few bytes per frame for most programs.
+pub struct Rewind{
+    newest : Option<Vec<u8>>,
+    older : VecDeque<Delta>,
+    /// Most states kept, the oldest is dropped past this
+    pub capacity : usize
+}
+
+impl Rewind{
+    pub fn new(capacity: usize) -> Rewind{
+        Rewind {newest : None, older : VecDeque::new(), capacity}
+    }
+
+    /// Records the current state
+    pub fn push(&mut self, processor: &Cpu){
+        let state = processor.save_state();
+
+        if let Some(newest) = self.newest.take(){
+            self.older.push_back(Delta::between(&state, &newest));
+            if self.older.len() >= self.capacity{
+                self.older.pop_front();
+            }
+        }
+        self.newest = Some(state);
+    }
+
+    /// Restores the most recently recorded state and forgets it, returns false when there is nothing left
+    pub fn rewind(&mut self, processor: &mut Cpu) -> bool{
+        let Some(mut state) = self.newest.take() else {return false};
+        processor.load_state(&state).expect("rewind states are written by save_state");
+
+        if let Some(delta) = self.older.pop_back(){
+            delta.apply(&mut state);
+            self.newest = Some(state);
+        }
+
+        true
+    }
+
+    /// Number of states that can be rewound to
+    pub fn len(&self) -> usize{
+        self.newest.as_ref().map_or(0, |_| self.older.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.newest.is_none()
+    }
+
+    pub fn clear(&mut self){
+        self.newest = None;
+        self.older.clear();
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::Rewind;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn rewinds_in_order_and_drops_oldest(){
+        // ADD V0, 1 ; LD I, 0x300 ; LD [I], V0 ; JP 0x200
+        let rom = [0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00];
+        let mut processor = Cpu::new(&rom).unwrap();
+        let mut rewind = Rewind::new(3);
+
+        let mut states = Vec::new();
+        for _ in 0..5{
+            rewind.push(&processor);
+            states.push(processor.save_state());
+            processor.run(4).unwrap();
+        }
+        assert_eq!(rewind.len(), 3);
+
+        for state in states.iter().rev().take(3){
+            assert!(rewind.rewind(&mut processor));
+            assert_eq!(&processor.save_state(), state);
+        }
+        assert!(!rewind.rewind(&mut processor));
+        assert_eq!(processor.memory.addr_mem[0x300], 2);
+    }
+}
@@ -0,0 +1,94 @@
+/// Where RNDVxbyte gets its random bytes from. The whole state fits in a u64 so save states and rewinding can restore it.
+pub trait RandomSource{
+    fn next_byte(&mut self) -> u8;
+    fn state(&self) -> u64;
+    fn set_state(&mut self, state: u64);
+}
+
+/// SplitMix64, written out here so a seed gives the same bytes whatever version of rand is in use
+pub struct SeededRng{
+    state : u64
+}
+
+impl SeededRng{
+    pub fn new(seed: u64) -> SeededRng{
+        SeededRng {state : seed}
+    }
+}
+
+impl RandomSource for SeededRng{
+    fn next_byte(&mut self) -> u8{
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+
+        ((z ^ (z >> 31)) >> 56) as u8
+    }
+
+    fn state(&self) -> u64{
+        self.state
+    }
+
+    fn set_state(&mut self, state: u64){
+        self.state = state;
+    }
+}
+
+/// Plays back a fixed sequence of bytes, starting over once they run out
+pub struct ByteReplay{
+    bytes : Vec<u8>,
+    position : u64
+}
+
+impl ByteReplay{
+    /// Returns None for an empty sequence
+    pub fn new(bytes: Vec<u8>) -> Option<ByteReplay>{
+        (!bytes.is_empty()).then_some(ByteReplay {bytes, position : 0})
+    }
+}
+
+impl RandomSource for ByteReplay{
+    fn next_byte(&mut self) -> u8{
+        let byte = self.bytes[(self.position % self.bytes.len() as u64) as usize];
+        self.position += 1;
+
+        byte
+    }
+
+    fn state(&self) -> u64{
+        self.position
+    }
+
+    fn set_state(&mut self, state: u64){
+        self.position = state;
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{ByteReplay, RandomSource, SeededRng};
+
+    #[test]
+    fn seeded_rng_repeats_from_state(){
+        let mut rng = SeededRng::new(42);
+        let mut again = SeededRng::new(42);
+        let first: Vec<u8> = (0..8).map(|_| rng.next_byte()).collect();
+        let state = rng.state();
+        let second: Vec<u8> = (0..8).map(|_| rng.next_byte()).collect();
+
+        assert_eq!(first, (0..8).map(|_| again.next_byte()).collect::<Vec<u8>>());
+        assert_ne!(first, second);
+
+        rng.set_state(state);
+        assert_eq!((0..8).map(|_| rng.next_byte()).collect::<Vec<u8>>(), second);
+    }
+
+    #[test]
+    fn byte_replay_wraps(){
+        let mut rng = ByteReplay::new(vec![1, 2, 3]).unwrap();
+        assert_eq!((0..5).map(|_| rng.next_byte()).collect::<Vec<u8>>(), [1, 2, 3, 1, 2]);
+        assert!(ByteReplay::new(Vec::new()).is_none());
+    }
+}
@@ -0,0 +1,70 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use crate::display::Display;
+
+/// Scales the screen up by a whole number and turns it into packed 8-bit RGB, coloured with the display's palette
+pub fn rgb_bytes(display: &Display, scale: usize) -> Vec<u8>{
+    let colours = display.to_rgb();
+    let mut bytes = Vec::with_capacity(colours.len() * scale * scale * 3);
+
+    for row in colours.chunks(display.width){
+        let line: Vec<u8> = row.iter().flat_map(|&colour| std::iter::repeat_n(colour, scale))
+            .flat_map(|colour| [(colour >> 16) as u8, (colour >> 8) as u8, colour as u8]).collect();
+        for _ in 0..scale{
+            bytes.extend_from_slice(&line);
+        }
+    }
+
+    bytes
+}
+
+/// Writes the screen as a PNG, `scale` times its native size with no smoothing
+pub fn write_png(display: &Display, scale: usize, writer: impl Write) -> io::Result<()>{
+    let scale = scale.max(1);
+    let mut encoder = png::Encoder::new(writer, (display.width * scale) as u32, (display.height * scale) as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&rgb_bytes(display, scale))?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+pub fn save_png(display: &Display, scale: usize, path: &Path) -> io::Result<()>{
+    write_png(display, scale, BufWriter::new(File::create(path)?))
+}
+
+/// The first `<stem>-N.png` next to `base` that doesn't exist yet, so screenshots never overwrite each other
+pub fn next_free_path(base: &Path) -> PathBuf{
+    let stem = base.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    (1..).map(|index| base.with_file_name(format!("{}-{}.png", stem, index))).find(|path| !path.exists()).unwrap()
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::write_png;
+    use crate::display::Display;
+
+    #[test]
+    fn scales_with_palette(){
+        let mut display = Display::new();
+        display.palette[1] = 0x102030;
+        display.draw_sprite(0, 0, 8, &[0x80], 1, false);
+
+        let mut bytes = Vec::new();
+        write_png(&display, 3, &mut bytes).unwrap();
+
+        let mut reader = png::Decoder::new(bytes.as_slice()).read_info().unwrap();
+        let mut image = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut image).unwrap();
+        assert_eq!((info.width, info.height), (192, 96));
+
+        let pixel = |x: usize, y: usize| &image[(y * 192 + x) * 3..(y * 192 + x) * 3 + 3];
+        assert_eq!((pixel(0, 0), pixel(2, 2)), (&[0x10, 0x20, 0x30][..], &[0x10, 0x20, 0x30][..]));
+        assert_eq!((pixel(3, 0), pixel(0, 3)), (&[0, 0, 0][..], &[0, 0, 0][..]));
+    }
+}
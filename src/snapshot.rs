@@ -0,0 +1,178 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use crate::cpu::Cpu;
+use crate::display::Display;
+use crate::quirks::Quirks;
+use crate::timing::{Scheduler, Speed};
+
+/// Characters for a pixel that is off, in plane 1, in plane 2 and in both
+const CELLS: [char; 4] = ['.', '#', '+', '@'];
+
+/// Frames to run looking for a PC before giving up, a minute of emulated time
+const MAX_FRAMES: u64 = 3600;
+
+/// Set this environment variable to rewrite snapshots from the current output instead of checking them
+pub const UPDATE_VAR: &str = "UPDATE_SNAPSHOTS";
+
+/// Where to stop a snapshot run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Until{
+    /// After this many 60Hz frames
+    Frame(u64),
+    /// Just before the instruction at this address runs
+    Pc(u16)
+}
+
+/// Runs a ROM at 11 instructions a frame until `until`, returning the machine there
+pub fn run_rom(rom: &[u8], quirks: Quirks, until: Until) -> Result<Cpu, String>{
+    let mut processor = Cpu::with_quirks(rom, quirks).map_err(|err| err.to_string())?;
+    processor.set_seed(0);
+    let mut scheduler = Scheduler::new(Speed::Ipf(11), false);
+
+    let frames = match until{
+        Until::Frame(frames) => frames,
+        Until::Pc(_) => MAX_FRAMES
+    };
+    for _ in 0..frames{
+        let finished = scheduler.run_frame_with(&mut processor, |processor| match until{
+            Until::Pc(pc) if processor.memory.pc == pc => Ok(false),
+            _ => processor.step().map(|_| true)
+        }).map_err(|err| err.to_string())?;
+        if !finished{
+            return Ok(processor);
+        }
+    }
+
+    match until{
+        Until::Frame(_) => Ok(processor),
+        Until::Pc(pc) => Err(format!("PC never reached {:#05X} in {} frames", pc, MAX_FRAMES))
+    }
+}
+
+/// One line of `.#+@` per row of pixels
+pub fn to_ascii(display: &Display) -> String{
+    display.pixels.chunks(display.width).map(|row| row.iter().map(|&pixel| CELLS[(pixel & 0x3) as usize]).collect::<String>() + "\n").collect()
+}
+
+/// Plain PBM with a pixel set when either plane is on
+pub fn to_pbm(display: &Display) -> String{
+    let rows: String = display.pixels.chunks(display.width)
+        .map(|row| row.iter().map(|&pixel| if pixel & 0x3 != 0 {"1"} else {"0"}).collect::<Vec<&str>>().join(" ") + "\n").collect();
+
+    format!("P1\n{} {}\n{}", display.width, display.height, rows)
+}
+
+/// The pixel rows of an ASCII or plain PBM snapshot as `.#+@` characters
+fn rows(snapshot: &str) -> Vec<String>{
+    let Some(body) = snapshot.strip_prefix("P1") else {
+        return snapshot.lines().map(|line| line.to_string()).collect();
+    };
+
+    let mut values = body.lines().filter(|line| !line.starts_with('#')).flat_map(|line| line.split_whitespace());
+    let width: usize = values.next().and_then(|width| width.parse().ok()).unwrap_or(0);
+    let _height = values.next();
+    let cells: Vec<char> = values.flat_map(|value| value.chars()).map(|value| if value == '1' {CELLS[1]} else {CELLS[0]}).collect();
+
+    cells.chunks(width.max(1)).map(|row| row.iter().collect()).collect()
+}
+
+/// Shows expected and actual side by side with the differing pixels marked, or None when they match
+pub fn diff(expected: &str, actual: &str) -> Option<String>{
+    let (expected, actual) = (rows(expected), rows(actual));
+    if expected == actual{
+        return None;
+    }
+
+    let columns = expected.iter().chain(actual.iter()).map(|row| row.chars().count()).max().unwrap_or(0);
+    let width = columns.max("expected".len());
+    let mut text = format!("  {:width$}   {:width$}   differences\n", "expected", "actual");
+    for index in 0..expected.len().max(actual.len()){
+        let ours = expected.get(index).map_or("", |row| row.as_str());
+        let theirs = actual.get(index).map_or("", |row| row.as_str());
+        let marks: String = (0..columns).map(|column| if ours.chars().nth(column) == theirs.chars().nth(column) {' '} else {'^'}).collect();
+
+        let marker = if ours == theirs {' '} else {'>'};
+        text += format!("{} {:width$} | {:width$} | {}", marker, ours, theirs, marks).trim_end();
+        text.push('\n');
+    }
+
+    Some(text)
+}
+
+/// Compares the screen with the snapshot at `path`, as PBM if it ends in .pbm and ASCII otherwise. The snapshot is
+/// written instead when UPDATE_SNAPSHOTS is set, and a missing snapshot fails otherwise. Errors carry a visual diff.
+pub fn check_snapshot(display: &Display, path: &Path) -> Result<(), String>{
+    let actual = match path.extension(){
+        Some(extension) if extension == "pbm" => to_pbm(display),
+        _ => to_ascii(display)
+    };
+
+    if env::var_os(UPDATE_VAR).is_some(){
+        return fs::write(path, actual).map_err(|err| format!("Cannot write {}: {}", path.display(), err));
+    }
+    if !path.exists(){
+        return Err(format!("No snapshot at {}, rerun with {}=1 to write it", path.display(), UPDATE_VAR));
+    }
+
+    let expected = fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+    match diff(&expected, &actual){
+        Some(diff) => Err(format!("Screen differs from {}, rerun with {}=1 to accept it\n{}", path.display(), UPDATE_VAR, diff)),
+        None => Ok(())
+    }
+}
+
+/// Panics with a visual diff when the screen doesn't match the snapshot at `path`
+pub fn assert_snapshot(display: &Display, path: &Path){
+    if let Err(err) = check_snapshot(display, path){
+        panic!("{}", err);
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{check_snapshot, diff, run_rom, to_ascii, to_pbm, Until, UPDATE_VAR};
+    use crate::quirks::Quirks;
+    use std::env;
+    use std::path::Path;
+
+    // LD V0, 1 ; LD F, V0 ; DRW V0, V0, 5 ; JP 0x206
+    const ROM: [u8; 8] = [0x60, 0x01, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06];
+
+    #[test]
+    fn runs_to_pc(){
+        let processor = run_rom(&ROM, Quirks::default(), Until::Pc(0x204)).unwrap();
+        assert_eq!(processor.cycles, 2);
+        assert!(to_ascii(&processor.curr_buffer).chars().all(|cell| cell == '.' || cell == '\n'));
+
+        let processor = run_rom(&ROM, Quirks::default(), Until::Frame(1)).unwrap();
+        let ascii = to_ascii(&processor.curr_buffer);
+        assert_eq!(&ascii.lines().nth(1).unwrap()[..6], "...#..");
+        assert_eq!(&ascii.lines().nth(2).unwrap()[..6], "..##..");
+        assert!(to_pbm(&processor.curr_buffer).starts_with("P1\n64 32\n0 0 0 0"));
+        assert!(run_rom(&ROM, Quirks::default(), Until::Pc(0x300)).is_err());
+    }
+
+    #[test]
+    fn missing_snapshot_fails(){
+        if env::var_os(UPDATE_VAR).is_some(){
+            return;
+        }
+        let processor = run_rom(&ROM, Quirks::default(), Until::Frame(1)).unwrap();
+        let path = Path::new("tests/snapshots/missing.txt");
+
+        assert!(check_snapshot(&processor.curr_buffer, path).unwrap_err().starts_with("No snapshot at"));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn diff_marks_changed_pixels(){
+        assert_eq!(diff("..#\n...\n", "..#\n...\n"), None);
+
+        let text = diff("..#\n...\n", "P1\n3 2\n0 0 1\n0 1 0\n").unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[1], "  ..#      | ..#      |");
+        assert_eq!(lines[2], "> ...      | .#.      |  ^");
+    }
+}
@@ -0,0 +1,326 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use crate::cpu::Cpu;
+use crate::display::{Display, LORES_WIDTH, LORES_HEIGHT, HIRES_WIDTH, HIRES_HEIGHT};
+use crate::memory::Memory;
+use crate::opcode::{Opcode, Platform};
+use crate::quirks::Quirks;
+
+const MAGIC: &[u8; 4] = b"SC8S";
+
+/// Bumped whenever the layout written by save_state changes
+pub const STATE_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError{
+    NotAState,
+    UnsupportedVersion(u16),
+    Truncated,
+    /// The file parsed but describes a machine that cannot exist
+    Invalid(String),
+    Io(String)
+}
+
+impl fmt::Display for StateError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            StateError::NotAState => write!(f, "Not a save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "Save state version {} is not supported, expected {}", version, STATE_VERSION),
+            StateError::Truncated => write!(f, "Save state is truncated"),
+            StateError::Invalid(reason) => write!(f, "Invalid save state: {}", reason),
+            StateError::Io(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+struct Writer(Vec<u8>);
+
+impl Writer{
+    fn u8(&mut self, value: u8){
+        self.0.push(value);
+    }
+
+    fn bool(&mut self, value: bool){
+        self.0.push(value as u8);
+    }
+
+    fn u16(&mut self, value: u16){
+        self.0.extend(value.to_be_bytes());
+    }
+
+    fn u32(&mut self, value: u32){
+        self.0.extend(value.to_be_bytes());
+    }
+
+    fn u64(&mut self, value: u64){
+        self.0.extend(value.to_be_bytes());
+    }
+
+    /// Writes the length first so the reader knows how much follows
+    fn bytes(&mut self, bytes: &[u8]){
+        self.u32(bytes.len() as u32);
+        self.0.extend(bytes);
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_>{
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], StateError>{
+        if self.0.len() < N{
+            return Err(StateError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(N);
+        self.0 = rest;
+
+        Ok(head.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError>{
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, StateError>{
+        Ok(self.u8()? != 0)
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError>{
+        Ok(u16::from_be_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError>{
+        Ok(u32::from_be_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError>{
+        Ok(u64::from_be_bytes(self.take()?))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, StateError>{
+        let len = self.u32()? as usize;
+        if self.0.len() < len{
+            return Err(StateError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+
+        Ok(head.to_vec())
+    }
+}
+
+impl Cpu{
+    /// Serializes everything needed to carry on exactly where the program is now, quirks included
+    pub fn save_state(&self) -> Vec<u8>{
+        let mut out = Writer(Vec::new());
+        out.0.extend(MAGIC);
+        out.u16(STATE_VERSION);
+
+        let memory = &self.memory;
+        out.bytes(&memory.addr_mem);
+        out.0.extend(memory.reg);
+        out.u16(memory.i);
+        out.u16(memory.pc);
+        for slot in memory.stack{
+            out.bool(slot.is_some());
+            out.u16(slot.unwrap_or(0));
+        }
+        out.u8(memory.sp);
+        out.u8(memory.delay);
+        out.u8(memory.sound);
+        out.0.extend(memory.rpl);
+        out.0.extend(memory.pattern);
+        out.u8(memory.pitch);
+
+        let display = &self.curr_buffer;
+        out.bool(display.hires);
+        out.bytes(&display.pixels);
+        out.u8(display.plane);
+        for colour in display.palette{
+            out.u32(colour);
+        }
+
+        out.u16(self.opcode.code);
+        for pressed in self.keypad{
+            out.bool(pressed);
+        }
+        out.bytes(&self.key);
+        out.bool(self.exited);
+        out.bool(self.vblank_wait);
+
+        let quirks = self.quirks;
+        for quirk in [quirks.shift, quirks.load_store, quirks.jump, quirks.vf_reset, quirks.wrap, quirks.display_wait]{
+            out.bool(quirk);
+        }
+        out.u64(self.rng.state());
+        out.bool(memory.pattern_loaded);
+        out.u8(quirks.platform as u8);
+        out.u64(self.cycles);
+        out.bool(self.sounding);
+        out.u64(self.seed);
+
+        out.0
+    }
+
+    /// Restores a state written by save_state, leaving the machine untouched if it can't be read
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError>{
+        let mut input = Reader(data);
+        if &input.take::<4>().map_err(|_| StateError::NotAState)? != MAGIC{
+            return Err(StateError::NotAState);
+        }
+        let version = input.u16()?;
+        if version != STATE_VERSION{
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let addr_mem = input.bytes()?;
+        let reg = input.take()?;
+        let i = input.u16()?;
+        let pc = input.u16()?;
+        let mut stack = [None; 16];
+        for slot in stack.iter_mut(){
+            let used = input.bool()?;
+            let addr = input.u16()?;
+            *slot = used.then_some(addr);
+        }
+        let sp = input.u8()?;
+        if sp > 16{
+            return Err(StateError::Invalid(format!("stack pointer is {}", sp)));
+        }
+        let mut memory = Memory {addr_mem, reg, i, pc, stack, sp, delay : input.u8()?, sound : input.u8()?, rpl : input.take()?, pattern : input.take()?,
+            pattern_loaded : false, pitch : input.u8()?};
+
+        let hires = input.bool()?;
+        let (width, height) = if hires {(HIRES_WIDTH, HIRES_HEIGHT)} else {(LORES_WIDTH, LORES_HEIGHT)};
+        let pixels = input.bytes()?;
+        if pixels.len() != width * height{
+            return Err(StateError::Invalid(format!("screen has {} pixels", pixels.len())));
+        }
+        let plane = input.u8()?;
+        if plane > 3{
+            return Err(StateError::Invalid(format!("plane is {}", plane)));
+        }
+        let mut palette = [0; 4];
+        for colour in palette.iter_mut(){
+            *colour = input.u32()?;
+        }
+        let display = Display {width, height, hires, pixels, plane, palette};
+
+        let code = input.u16()?;
+        let mut keypad = [false; 16];
+        for pressed in keypad.iter_mut(){
+            *pressed = input.bool()?;
+        }
+        let key = input.bytes()?;
+        if key.iter().any(|&key| key > 0xF){
+            return Err(StateError::Invalid("queued key out of range".to_string()));
+        }
+        let (exited, vblank_wait) = (input.bool()?, input.bool()?);
+        let (shift, load_store, jump, vf_reset, wrap, display_wait) = (input.bool()?, input.bool()?, input.bool()?, input.bool()?, input.bool()?, input.bool()?);
+        let rng_state = input.u64()?;
+        memory.pattern_loaded = input.bool()?;
+        let platform = match input.u8()?{
+            0 => Platform::Chip8,
+            1 => Platform::Schip,
+            2 => Platform::XoChip,
+            platform => return Err(StateError::Invalid(format!("platform is {}", platform)))
+        };
+        let quirks = Quirks {shift, load_store, jump, vf_reset, wrap, display_wait, platform};
+        let (cycles, sounding, seed) = (input.u64()?, input.bool()?, input.u64()?);
+        if memory.addr_mem.len() != quirks.platform.memory_size(){
+            return Err(StateError::Invalid(format!("memory is {} bytes", memory.addr_mem.len())));
+        }
+
+        self.memory = memory;
+        self.curr_buffer = display;
+        self.opcode = Opcode {code, kind : Opcode::find_kind(code).ok()};
+        self.keypad = keypad;
+        self.key = key;
+        self.draw_flag = true;
+        self.exited = exited;
+        self.vblank_wait = vblank_wait;
+        self.quirks = quirks;
+        self.mem_accesses.clear();
+        (self.cycles, self.sounding, self.seed) = (cycles, sounding, seed);
+        self.rng.set_state(rng_state);
+
+        Ok(())
+    }
+
+    pub fn save_state_file(&self, path: &Path) -> Result<(), StateError>{
+        fs::write(path, self.save_state()).map_err(|err| StateError::Io(format!("Cannot write {}: {}", path.display(), err)))
+    }
+
+    pub fn load_state_file(&mut self, path: &Path) -> Result<(), StateError>{
+        let data = fs::read(path).map_err(|err| StateError::Io(format!("Cannot read {}: {}", path.display(), err)))?;
+        self.load_state(&data)
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{StateError, STATE_VERSION};
+    use crate::cpu::Cpu;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn restores_exactly(){
+        // HIGH ; LD V0, 0x2A ; LD I, 0x300 ; LD B, V0 ; CALL 0x20C ; JP 0x20A ; DRW V0, V0, 0 ; JP 0x20E
+        let rom = [0x00, 0xFF, 0x60, 0x2A, 0xA3, 0x00, 0xF0, 0x33, 0x22, 0x0C, 0x12, 0x0A, 0xD0, 0x00, 0x12, 0x0E];
+        let mut processor = Cpu::new(&rom).unwrap();
+        processor.quirks = Quirks::schip();
+        processor.run(6).unwrap();
+        processor.set_keypad([true; 16]);
+        let saved = processor.save_state();
+
+        let mut restored = Cpu::new(&[]).unwrap();
+        restored.load_state(&saved).unwrap();
+        assert_eq!(restored.save_state(), saved);
+        assert_eq!(restored.memory.stack[0], Some(0x208));
+        assert_eq!(restored.curr_buffer.width, 128);
+        assert_eq!(restored.quirks, Quirks::schip());
+        assert_eq!((restored.cycles, restored.seed), (6, processor.seed));
+
+        processor.run(10).unwrap();
+        restored.run(10).unwrap();
+        assert_eq!(restored.save_state(), processor.save_state());
+    }
+
+    #[test]
+    fn restores_random_sequence(){
+        // RND V0, 0xFF ; JP 0x200
+        let mut processor = Cpu::new(&[0xC0, 0xFF, 0x12, 0x00]).unwrap();
+        processor.run(6).unwrap();
+        let saved = processor.save_state();
+
+        processor.run(2).unwrap();
+        let expected = processor.memory.reg[0];
+        processor.run(8).unwrap();
+
+        processor.load_state(&saved).unwrap();
+        processor.run(2).unwrap();
+        assert_eq!(processor.memory.reg[0], expected);
+    }
+
+    #[test]
+    fn rejects_bad_files(){
+        let mut processor = Cpu::new(&[0x00, 0xE0]).unwrap();
+        let mut saved = processor.save_state();
+
+        assert_eq!(processor.load_state(b"PNG"), Err(StateError::NotAState));
+        assert_eq!(processor.load_state(&saved[..saved.len() - 1]), Err(StateError::Truncated));
+
+        let mut plane = saved.clone();
+        // The plane is followed by the palette and 57 bytes of machine state with no queued keys
+        let offset = plane.len() - 16 - 57 - 1;
+        assert_eq!(plane[offset], 1);
+        plane[offset] = 4;
+        assert_eq!(processor.load_state(&plane), Err(StateError::Invalid("plane is 4".to_string())));
+
+        saved[4..6].copy_from_slice(&(STATE_VERSION + 1).to_be_bytes());
+        assert_eq!(processor.load_state(&saved), Err(StateError::UnsupportedVersion(STATE_VERSION + 1)));
+    }
+}
@@ -0,0 +1,206 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use crate::cpu::Cpu;
+use crate::quirks::{Preset, Quirks};
+use crate::screenshot::{rgb_bytes, save_png};
+use crate::timing::{Scheduler, Speed};
+
+/// A key held down from frame `from` up to but not including frame `to`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyHold{
+    pub key : u8,
+    pub from : u64,
+    pub to : u64
+}
+
+/// A ROM to run headlessly and the screen it should end on
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestRom{
+    pub name : String,
+    pub rom : PathBuf,
+    pub frames : u64,
+    pub quirks : Quirks,
+    pub speed : Speed,
+    pub keys : Vec<KeyHold>,
+    /// PNG of the expected final screen
+    pub golden : PathBuf
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome{
+    Pass,
+    Fail(String),
+    /// The golden image was written from this run
+    Updated
+}
+
+impl TestRom{
+    /// Runs the ROM for its frames with its keys held, returning the machine at the end
+    pub fn run(&self) -> Result<Cpu, String>{
+        let rom = fs::read(&self.rom).map_err(|err| format!("Cannot read {}: {}", self.rom.display(), err))?;
+        let mut processor = Cpu::with_quirks(&rom, self.quirks).map_err(|err| err.to_string())?;
+        processor.set_seed(0);
+        let mut scheduler = Scheduler::new(self.speed, false);
+
+        for frame in 0..self.frames{
+            processor.set_keypad(std::array::from_fn(|key| self.keys.iter().any(|hold| hold.key as usize == key && (hold.from..hold.to).contains(&frame))));
+            scheduler.run_frame(&mut processor).map_err(|err| format!("Frame {}: {}", frame, err))?;
+        }
+
+        Ok(processor)
+    }
+
+    /// Runs the ROM and compares its screen with the golden image, writing the image instead when `update` is set
+    pub fn check(&self, update: bool) -> Outcome{
+        let processor = match self.run(){
+            Ok(processor) => processor,
+            Err(err) => return Outcome::Fail(err)
+        };
+        let display = &processor.curr_buffer;
+
+        if update{
+            return match save_png(display, 1, &self.golden){
+                Ok(()) => Outcome::Updated,
+                Err(err) => Outcome::Fail(format!("Cannot write {}: {}", self.golden.display(), err))
+            };
+        }
+
+        let (width, height, golden) = match load_png(&self.golden){
+            Ok(image) => image,
+            Err(err) => return Outcome::Fail(format!("Cannot read {}: {}", self.golden.display(), err))
+        };
+        if (width, height) != (display.width, display.height){
+            return Outcome::Fail(format!("Screen is {}x{}, golden image is {}x{}", display.width, display.height, width, height));
+        }
+
+        let differing = rgb_bytes(display, 1).chunks(3).zip(golden.chunks(3)).filter(|(ours, theirs)| ours != theirs).count();
+        match differing{
+            0 => Outcome::Pass,
+            count => Outcome::Fail(format!("{} pixels differ from {}", count, self.golden.display()))
+        }
+    }
+}
+
+/// Reads an 8-bit RGB PNG as its width, height and pixel bytes
+fn load_png(path: &Path) -> Result<(usize, usize, Vec<u8>), String>{
+    let decoder = png::Decoder::new(File::open(path).map_err(|err| err.to_string())?);
+    let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+    let mut image = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut image).map_err(|err| err.to_string())?;
+    if info.color_type != png::ColorType::Rgb || info.bit_depth != png::BitDepth::Eight{
+        return Err("expected an 8-bit RGB image".to_string());
+    }
+    image.truncate(info.buffer_size());
+
+    Ok((info.width as usize, info.height as usize, image))
+}
+
+/// Parses a manifest of one test per line: a name, a ROM path, a frame count, then any of
+/// `quirks=PRESET[,QUIRK=0|1,...][,platform=PLATFORM]`, `speed=SPEED` and `keys=KEY@FROM-TO,...` with hex keys. Paths are relative to `dir` and golden images live in
+/// `dir/golden/NAME.png`. Blank lines and lines starting with # are skipped.
+pub fn parse_manifest(text: &str, dir: &Path) -> Result<Vec<TestRom>, String>{
+    text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#')).map(|(index, line)| {
+        let bad = |what: &str| format!("Line {}: {}", index + 1, what);
+        let mut fields = line.split_whitespace();
+        let (Some(name), Some(rom), Some(frames)) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(bad("expected a name, a ROM and a frame count"));
+        };
+
+        let mut test = TestRom {name : name.to_string(), rom : dir.join(rom), frames : frames.parse().map_err(|_| bad("bad frame count"))?,
+            quirks : Preset::CosmacVip.into(), speed : Speed::Ipf(11), keys : Vec::new(), golden : dir.join("golden").join(format!("{}.png", name))};
+
+        for field in fields{
+            match field.split_once('='){
+                Some(("quirks", quirks)) => test.quirks = parse_quirks(quirks).map_err(|err| bad(&err))?,
+                Some(("speed", speed)) => test.speed = speed.parse().map_err(|err: String| bad(&err))?,
+                Some(("keys", keys)) => test.keys = keys.split(',').map(|hold| parse_hold(hold).ok_or_else(|| bad(&format!("bad key hold {}", hold)))).collect::<Result<_, _>>()?,
+                _ => return Err(bad(&format!("unknown option {}", field)))
+            }
+        }
+
+        Ok(test)
+    }).collect()
+}
+
+/// Parses a preset followed by the quirks that differ from it, such as `vip,load_store=1,jump=0`
+fn parse_quirks(spec: &str) -> Result<Quirks, String>{
+    let mut fields = spec.split(',');
+    let mut quirks: Quirks = fields.next().unwrap_or_default().parse::<Preset>()?.into();
+
+    for field in fields{
+        let Some((name, value)) = field.split_once('=') else {
+            return Err(format!("expected QUIRK=VALUE, not {}", field));
+        };
+        if name == "platform"{
+            quirks.platform = value.parse()?;
+            continue;
+        }
+
+        let quirk = match name{
+            "shift" => &mut quirks.shift,
+            "load_store" => &mut quirks.load_store,
+            "jump" => &mut quirks.jump,
+            "vf_reset" => &mut quirks.vf_reset,
+            "wrap" => &mut quirks.wrap,
+            "display_wait" => &mut quirks.display_wait,
+            _ => return Err(format!("unknown quirk {}", name))
+        };
+        *quirk = match value{
+            "0" => false,
+            "1" => true,
+            _ => return Err(format!("{} must be 0 or 1, not {}", name, value))
+        };
+    }
+
+    Ok(quirks)
+}
+
+/// Parses `KEY@FROM-TO`
+fn parse_hold(hold: &str) -> Option<KeyHold>{
+    let (key, frames) = hold.split_once('@')?;
+    let (from, to) = frames.split_once('-')?;
+    let key = u8::from_str_radix(key, 16).ok().filter(|&key| key < 16)?;
+
+    Some(KeyHold {key, from : from.parse().ok()?, to : to.parse().ok()?})
+}
+
+pub fn load_manifest(path: &Path) -> Result<Vec<TestRom>, String>{
+    let text = fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+    parse_manifest(&text, path.parent().unwrap_or(Path::new(".")))
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{parse_manifest, KeyHold, Outcome};
+    use crate::opcode::Platform;
+    use crate::quirks::Quirks;
+    use crate::timing::Speed;
+    use std::path::Path;
+
+    #[test]
+    fn parses_manifest(){
+        let tests = parse_manifest("# name rom frames\n\nkeypad keypad.ch8 200 quirks=schip speed=vip keys=A@10-20,0@30-31\n", Path::new("roms")).unwrap();
+
+        assert_eq!(tests.len(), 1);
+        assert_eq!(tests[0].rom, Path::new("roms/keypad.ch8"));
+        assert_eq!(tests[0].golden, Path::new("roms/golden/keypad.png"));
+        assert_eq!(tests[0].speed, Speed::CosmacVip);
+        assert_eq!(tests[0].keys, [KeyHold {key : 10, from : 10, to : 20}, KeyHold {key : 0, from : 30, to : 31}]);
+        assert!(parse_manifest("keypad keypad.ch8 200 keys=G@1-2", Path::new(".")).is_err());
+
+        let tests = parse_manifest("c8_test c8_test.c8 120 quirks=vip,load_store=1,jump=0,platform=schip", Path::new(".")).unwrap();
+        assert_eq!(tests[0].quirks, Quirks {load_store : true, platform : Platform::Schip, ..Quirks::cosmac_vip()});
+        assert!(parse_manifest("c8_test c8_test.c8 120 quirks=vip,jump=2", Path::new(".")).is_err());
+        assert!(parse_manifest("c8_test c8_test.c8 120 quirks=vip,speed=1", Path::new(".")).is_err());
+        assert!(parse_manifest("keypad keypad.ch8", Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn missing_golden_fails(){
+        let mut test = parse_manifest("opcodes test_opcode.ch8 10", Path::new("TestRoms")).unwrap().remove(0);
+        test.golden = Path::new("TestRoms/golden/missing.png").to_path_buf();
+
+        assert!(matches!(test.check(false), Outcome::Fail(_)));
+    }
+}
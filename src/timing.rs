@@ -0,0 +1,199 @@
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::cpu::Cpu;
+use crate::error::EmulatorError;
+use crate::opcode::OpcodeTypes;
+
+pub const FRAMES_PER_SECOND: u64 = 60;
+
+/// COSMAC VIP machine cycles in a 60Hz frame, its 1.7609 MHz clock takes 8 clock pulses per machine cycle
+pub const VIP_CYCLES_PER_FRAME: i64 = 3668;
+
+/// Frame pacing gives up catching up once it is this many frames behind
+const MAX_LAG_FRAMES: u64 = 3;
+
+/// How much runs in each 60Hz frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speed{
+    /// A fixed number of instructions per frame
+    Ipf(u32),
+    /// As many instructions as fit in a frame of COSMAC VIP machine cycles
+    CosmacVip
+}
+
+impl FromStr for Speed{
+    type Err = String;
+
+    /// Parses an instruction count of at least 1 or `vip`
+    fn from_str(text: &str) -> Result<Self, Self::Err>{
+        if text.eq_ignore_ascii_case("vip"){
+            return Ok(Speed::CosmacVip);
+        }
+        text.parse().ok().filter(|&count| count > 0).map(Speed::Ipf).ok_or_else(|| format!("Bad speed {}, expected instructions per frame or vip", text))
+    }
+}
+
+/// Approximate machine cycles the VIP interpreter spends on an instruction. Extension instructions did not exist
+/// on the VIP and are given a nominal cost.
+pub fn vip_cycles(kind: OpcodeTypes, code: u16) -> i64{
+    let x = ((code & 0x0F00) >> 8) as i64;
+    let n = (code & 0x000F) as i64;
+
+    match kind{
+        OpcodeTypes::CLS => 3078,
+        OpcodeTypes::LDVxbyte => 6,
+        OpcodeTypes::RET | OpcodeTypes::SEVxByte | OpcodeTypes::SNEVxByte | OpcodeTypes::ADDVxbyte | OpcodeTypes::LDVxDT |
+        OpcodeTypes::LDVxK | OpcodeTypes::LDDTVx | OpcodeTypes::LDSTVx => 10,
+        OpcodeTypes::JPAddr | OpcodeTypes::LDIAddr => 12,
+        OpcodeTypes::SEVxVy | OpcodeTypes::SNEVxVy | OpcodeTypes::SKPVx | OpcodeTypes::SKNPVx => 14,
+        OpcodeTypes::ADDIVx | OpcodeTypes::LDFVx => 16,
+        OpcodeTypes::JPV0Addr => 22,
+        OpcodeTypes::CALLAddr => 26,
+        OpcodeTypes::RNDVxbyte => 36,
+        OpcodeTypes::LDVxVy | OpcodeTypes::ORVxVy | OpcodeTypes::ANDVxVy | OpcodeTypes::XORVxVy | OpcodeTypes::ADDVxVy |
+        OpcodeTypes::SUBVxVy | OpcodeTypes::SHRVxVy | OpcodeTypes::SUBNVxVy | OpcodeTypes::SHLVxVy => 44,
+        OpcodeTypes::LDBVx => 152,
+        OpcodeTypes::LDIVx | OpcodeTypes::LDVxI => 14 + 14 * (x + 1),
+        OpcodeTypes::DRWVxVyNibble => 22 + 68 * if n == 0 {16} else {n},
+        _ => 10
+    }
+}
+
+/// Runs instructions in 60Hz frames, ticking the timers once per frame and optionally sleeping to keep real time
+pub struct Scheduler{
+    pub speed : Speed,
+    /// Sleep between frames so a second of emulation takes a second, turned off for tests and fast forward
+    pub throttle : bool,
+    /// Frames run so far
+    pub frames : u64,
+    /// VIP machine cycles carried over into the next frame
+    budget : i64,
+    /// When pacing started and how many frames have been paced since
+    epoch : Option<(Instant, u64)>
+}
+
+impl Scheduler{
+    pub fn new(speed: Speed, throttle: bool) -> Scheduler{
+        Scheduler {speed, throttle, frames : 0, budget : 0, epoch : None}
+    }
+
+    /// Runs one frame of instructions and then ticks the timers
+    pub fn run_frame(&mut self, processor: &mut Cpu) -> Result<(), EmulatorError>{
+        self.run_frame_with(processor, |processor| processor.step().map(|_| true))?;
+        Ok(())
+    }
+
+    /// Like run_frame, but each instruction is run by `step`, which returns false to end the frame early without
+    /// ticking the timers, as a debugger does when it stops. Returns whether the frame finished.
+    pub fn run_frame_with(&mut self, processor: &mut Cpu, mut step: impl FnMut(&mut Cpu) -> Result<bool, EmulatorError>) -> Result<bool, EmulatorError>{
+        let mut executed = 0;
+        if self.speed == Speed::CosmacVip{
+            self.budget += VIP_CYCLES_PER_FRAME;
+        }
+
+        loop{
+            let more = match self.speed{
+                Speed::Ipf(count) => executed < count,
+                Speed::CosmacVip => self.budget > 0
+            };
+            // A display wait or EXIT leaves nothing to run until the next frame
+            if !more || processor.vblank_wait || processor.exited{
+                break;
+            }
+
+            let cycles = processor.cycles;
+            if !step(processor)?{
+                return Ok(false);
+            }
+            if processor.cycles != cycles{
+                executed += 1;
+                if let Some(kind) = processor.opcode.kind{
+                    self.budget -= vip_cycles(kind, processor.opcode.code);
+                }
+            }
+        }
+
+        self.budget = self.budget.min(0);
+        processor.tick_timers();
+        self.frames += 1;
+
+        Ok(true)
+    }
+
+    /// Sleeps until the next frame is due when throttled
+    pub fn wait(&mut self){
+        if !self.throttle{
+            return;
+        }
+
+        let now = Instant::now();
+        let (start, paced) = *self.epoch.get_or_insert((now, 0));
+        let due = start + Duration::from_nanos((paced + 1) * 1_000_000_000 / FRAMES_PER_SECOND);
+
+        if due > now{
+            thread::sleep(due - now);
+            self.epoch = Some((start, paced + 1));
+        }
+        else if now - due > Duration::from_nanos(MAX_LAG_FRAMES * 1_000_000_000 / FRAMES_PER_SECOND){
+            self.epoch = Some((now, 0));
+        }
+        else{
+            self.epoch = Some((start, paced + 1));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{Scheduler, Speed};
+    use crate::cpu::Cpu;
+    use crate::quirks::Quirks;
+    use std::time::Instant;
+
+    // LD DT, V0 ; ADD V1, 1 ; JP 0x202
+    const ROM: [u8; 6] = [0xF0, 0x15, 0x71, 0x01, 0x12, 0x02];
+
+    #[test]
+    fn fixed_instructions_per_frame(){
+        let mut processor = Cpu::new(&ROM).unwrap();
+        processor.memory.reg[0] = 10;
+        let mut scheduler = Scheduler::new(Speed::Ipf(9), false);
+
+        scheduler.run_frame(&mut processor).unwrap();
+        assert_eq!((processor.cycles, processor.memory.reg[1], processor.memory.delay), (9, 4, 9));
+
+        let start = Instant::now();
+        for _ in 0..3{
+            scheduler.run_frame(&mut processor).unwrap();
+            scheduler.wait();
+        }
+        assert!(start.elapsed().as_millis() < 30);
+        assert_eq!((processor.cycles, processor.memory.delay, scheduler.frames), (36, 6, 4));
+    }
+
+    #[test]
+    fn vip_cycles_and_display_wait(){
+        let mut processor = Cpu::new(&ROM).unwrap();
+        let mut scheduler = Scheduler::new(Speed::CosmacVip, false);
+        scheduler.run_frame(&mut processor).unwrap();
+        // LD DT takes 10 cycles, then each ADD and JP pair takes 22
+        assert_eq!(processor.cycles, 1 + 2 * 166 + 1);
+
+        // DRW V0, V0, 1 ; JP 0x200
+        let mut processor = Cpu::new(&[0xD0, 0x01, 0x12, 0x00]).unwrap();
+        processor.quirks = Quirks::cosmac_vip();
+        let mut scheduler = Scheduler::new(Speed::Ipf(100), false);
+        scheduler.run_frame(&mut processor).unwrap();
+        assert_eq!(processor.cycles, 1);
+    }
+
+    #[test]
+    fn speed_parses(){
+        assert_eq!("VIP".parse::<Speed>(), Ok(Speed::CosmacVip));
+        assert_eq!("15".parse::<Speed>(), Ok(Speed::Ipf(15)));
+        assert!("fast".parse::<Speed>().is_err());
+        assert_eq!("0".parse::<Speed>(), Err("Bad speed 0, expected instructions per frame or vip".to_string()));
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use crate::cpu::Cpu;
+use crate::disasm::mnemonic;
+use crate::error::EmulatorError;
+use crate::opcode::OpcodeTypes;
+use crate::timing::{Scheduler, Speed};
+
+/// The machine just before one instruction runs, as another emulator would dump it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceEntry{
+    pub pc : u16,
+    /// The instruction at the pc
+    pub opcode : u16,
+    pub reg : [u8; 16],
+    pub i : u16,
+    pub sp : u8,
+    pub delay : u8,
+    pub sound : u8
+}
+
+impl TraceEntry{
+    pub fn capture(processor: &Cpu) -> TraceEntry{
+        let memory = &processor.memory;
+        let pc = memory.pc as usize;
+        let opcode = match (memory.addr_mem.get(pc), memory.addr_mem.get(pc + 1)){
+            (Some(&high), Some(&low)) => u16::from_be_bytes([high, low]),
+            _ => 0
+        };
+
+        TraceEntry {pc : memory.pc, opcode, reg : memory.reg, i : memory.i, sp : memory.sp, delay : memory.delay, sound : memory.sound}
+    }
+
+    /// Each field's name and value as written in a trace line
+    fn fields(&self) -> Vec<(String, String)>{
+        let mut fields = vec![("PC".to_string(), format!("{:04X}", self.pc)), ("OP".to_string(), format!("{:04X}", self.opcode))];
+        fields.extend(self.reg.iter().enumerate().map(|(num, value)| (format!("V{:X}", num), format!("{:02X}", value))));
+        fields.extend([("I".to_string(), format!("{:04X}", self.i)), ("SP".to_string(), format!("{:X}", self.sp)),
+            ("DT".to_string(), format!("{:02X}", self.delay)), ("ST".to_string(), format!("{:02X}", self.sound))]);
+
+        fields
+    }
+}
+
+/// `PC=0200 OP=00E0 V0=00 ... VF=00 I=0000 SP=0 DT=00 ST=00`
+impl fmt::Display for TraceEntry{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        let fields: Vec<String> = self.fields().into_iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        write!(f, "{}", fields.join(" "))
+    }
+}
+
+/// Parses a line of hex `NAME=VALUE` fields in any order, with names in any case and values optionally starting
+/// with 0x. Every field of the Display format is needed, fields with other names are ignored.
+impl FromStr for TraceEntry{
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err>{
+        let mut values: [Option<u16>; 22] = [None; 22];
+        for field in line.split_whitespace(){
+            let Some((name, value)) = field.split_once('=') else {
+                return Err(format!("Expected NAME=VALUE, not {}", field));
+            };
+            let name = name.to_ascii_uppercase();
+            let slot = match name.as_str(){
+                "PC" => 16,
+                "OP" => 17,
+                "I" => 18,
+                "SP" => 19,
+                "DT" => 20,
+                "ST" => 21,
+                _ => match name.strip_prefix('V').and_then(|num| u8::from_str_radix(num, 16).ok()).filter(|_| name.len() == 2){
+                    Some(num) => num as usize,
+                    None => continue
+                }
+            };
+            let digits = value.trim_start_matches("0x").trim_start_matches("0X");
+            values[slot] = Some(u16::from_str_radix(digits, 16).map_err(|_| format!("Bad value {}", field))?);
+        }
+
+        let value = |slot: usize| values[slot].ok_or_else(|| format!("Missing {}", if slot < 16 {format!("V{:X}", slot)} else {["PC", "OP", "I", "SP", "DT", "ST"][slot - 16].to_string()}));
+        let byte = |slot: usize| value(slot).and_then(|value| u8::try_from(value).map_err(|_| format!("{:X} does not fit in a byte", value)));
+        let mut reg = [0; 16];
+        for (num, slot) in reg.iter_mut().enumerate(){
+            *slot = byte(num)?;
+        }
+
+        Ok(TraceEntry {pc : value(16)?, opcode : value(17)?, reg, i : value(18)?, sp : byte(19)?, delay : byte(20)?, sound : byte(21)?})
+    }
+}
+
+/// Parses a trace of one entry per line, skipping blank lines and lines starting with #
+pub fn parse_trace(text: &str) -> Result<Vec<TraceEntry>, String>{
+    text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(index, line)| line.parse().map_err(|err| format!("Line {}: {}", index + 1, err))).collect()
+}
+
+pub fn load_trace(path: &Path) -> Result<Vec<TraceEntry>, String>{
+    let text = fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+    parse_trace(&text)
+}
+
+/// Runs `count` instructions, or until the program exits, recording the machine before each one. Timers tick
+/// every frame of `speed`.
+pub fn record(processor: &mut Cpu, count: usize, speed: Speed) -> Result<Vec<TraceEntry>, EmulatorError>{
+    let mut scheduler = Scheduler::new(speed, false);
+    let mut trace = Vec::new();
+
+    while trace.len() < count && !processor.exited{
+        scheduler.run_frame_with(processor, |processor| {
+            if trace.len() == count{
+                return Ok(false);
+            }
+            trace.push(TraceEntry::capture(processor));
+            processor.step().map(|_| true)
+        })?;
+    }
+
+    Ok(trace)
+}
+
+/// Steps the machine alongside a reference trace with timers ticking every frame of `speed`, returning how many
+/// instructions matched. Stops at the first entry that differs with the two side by side. Random numbers can't
+/// match another emulator's, so the register a RNDVxbyte writes is taken from the reference.
+pub fn compare(processor: &mut Cpu, trace: &[TraceEntry], speed: Speed) -> Result<usize, String>{
+    let mut scheduler = Scheduler::new(speed, false);
+    let mut index = 0;
+    let mut divergence = None;
+
+    while index < trace.len() && divergence.is_none(){
+        if processor.exited{
+            return Err(format!("Program exited after {} of {} instructions", index, trace.len()));
+        }
+
+        scheduler.run_frame_with(processor, |processor| {
+            let Some(expected) = trace.get(index) else {
+                return Ok(false);
+            };
+            if index > 0 && processor.opcode.kind == Some(OpcodeTypes::RNDVxbyte){
+                let x = ((processor.opcode.code >> 8) & 0xF) as usize;
+                processor.memory.reg[x] = expected.reg[x];
+            }
+
+            let actual = TraceEntry::capture(processor);
+            if actual != *expected{
+                divergence = Some(side_by_side(index, index.checked_sub(1).map(|previous| &trace[previous]), expected, &actual));
+                return Ok(false);
+            }
+            processor.step()?;
+            index += 1;
+            Ok(true)
+        }).map_err(|err| format!("Instruction {}: {}", index, err))?;
+    }
+
+    match divergence{
+        Some(diff) => Err(diff),
+        None => Ok(index)
+    }
+}
+
+/// Every field of both entries with the differing ones marked, headed by the instruction that ran just before
+fn side_by_side(index: usize, previous: Option<&TraceEntry>, expected: &TraceEntry, actual: &TraceEntry) -> String{
+    let mut text = format!("Diverged at instruction {}", index);
+    if let Some(previous) = previous{
+        let name = mnemonic(previous.opcode, 0).unwrap_or_else(|_| "???".to_string());
+        write!(text, ", after {:04X} {} at {:#05X}", previous.opcode, name, previous.pc).unwrap();
+    }
+    writeln!(text, "\n       reference  ours").unwrap();
+
+    for ((name, reference), (_, ours)) in expected.fields().into_iter().zip(actual.fields()){
+        let marker = if reference == ours {' '} else {'>'};
+        writeln!(text, "{} {:4} {:10} {}", marker, name, reference, ours).unwrap();
+    }
+
+    text
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{compare, parse_trace, record, TraceEntry};
+    use crate::cpu::Cpu;
+    use crate::timing::Speed;
+
+    // LD V1, 5 ; LD V2, 3 ; SUB V1, V2 ; LD DT, V1 ; JP 0x208
+    const ROM: [u8; 10] = [0x61, 0x05, 0x62, 0x03, 0x81, 0x25, 0xF1, 0x15, 0x12, 0x08];
+
+    #[test]
+    fn parses_entries(){
+        let entry = TraceEntry {pc : 0x200, opcode : 0x00E0, reg : [0xAB; 16], i : 0x123, sp : 2, delay : 0x3C, sound : 0};
+        assert_eq!(entry.to_string().parse::<TraceEntry>(), Ok(entry));
+
+        let loose = "# header\n\nop=0x00e0 pc=0x200 v0=ab v1=ab v2=ab v3=ab v4=ab v5=ab v6=ab v7=ab v8=ab v9=ab va=ab vb=ab vc=ab vd=ab ve=ab vf=ab i=123 sp=2 dt=3c st=0 cycle=7\n";
+        assert_eq!(parse_trace(loose), Ok(vec![entry]));
+        assert!(parse_trace("PC=0200 OP=00E0").unwrap_err().starts_with("Line 1: Missing V0"));
+    }
+
+    #[test]
+    fn stops_at_first_divergence(){
+        let trace = record(&mut Cpu::new(&ROM).unwrap(), 20, Speed::Ipf(4)).unwrap();
+        assert_eq!(trace.len(), 20);
+        assert_eq!(trace[4].delay, 1);
+        assert_eq!(compare(&mut Cpu::new(&ROM).unwrap(), &trace, Speed::Ipf(4)), Ok(20));
+
+        let mut reference = trace.clone();
+        for entry in reference[3..].iter_mut(){
+            entry.reg[0xF] = 0;
+        }
+        let diff = compare(&mut Cpu::new(&ROM).unwrap(), &reference, Speed::Ipf(4)).unwrap_err();
+        assert!(diff.starts_with("Diverged at instruction 3, after 8125 SUB V1, V2 at 0x204\n"), "{}", diff);
+        assert!(diff.contains("\n> VF   00         01\n"), "{}", diff);
+        assert!(diff.contains("\n  V1   02         02\n"), "{}", diff);
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crossterm::{cursor, event, execute, queue, style, terminal};
+use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags};
+use chip_8::Cpu;
+use chip_8::display::Display;
+use crate::frontend::{Frontend, Hotkey};
+
+/// How long a key counts as held after the terminal last sent it, for terminals that never report releases.
+/// Long enough to bridge the pause before the terminal starts repeating a held key.
+const HOLD: Duration = Duration::from_millis(300);
+
+const KEYS: [(char, u8); 16] = [
+    ('1', 1), ('2', 2), ('3', 3), ('4', 12),
+    ('q', 4), ('w', 5), ('e', 6), ('r', 13),
+    ('a', 7), ('s', 8), ('d', 9), ('f', 14),
+    ('z', 10), ('x', 0), ('c', 11), ('v', 15)
+];
+
+const HOTKEYS: [(u8, Hotkey); 3] = [(5, Hotkey::SaveState), (9, Hotkey::LoadState), (12, Hotkey::Screenshot)];
+
+/// Set while the terminal is taken over, so exiting on an error can hand it back
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static RELEASES: AtomicBool = AtomicBool::new(false);
+
+/// Draws the screen in the terminal two pixels to a character cell with upper half blocks, taking keys from the tty.
+/// Lores needs a 64 column terminal and hires 128.
+pub struct TerminalFrontend{
+    /// Whether the terminal reports key releases, otherwise keys are held for HOLD after each press
+    releases : bool,
+    /// Keys down and when the terminal last sent them
+    held : HashMap<KeyCode, Instant>,
+    hotkeys : Vec<Hotkey>,
+    status : String,
+    /// The last screen drawn, without cursor movement so it can also be printed inline
+    picture : Vec<u8>,
+    /// Whether the picture has been printed since it changed
+    printed : bool,
+    size : (usize, usize),
+    redraw : bool,
+    open : bool
+}
+
+impl TerminalFrontend{
+    pub fn new() -> io::Result<TerminalFrontend>{
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        let mut frontend = TerminalFrontend {releases, held : HashMap::new(), hotkeys : Vec::new(), status : String::new(),
+            picture : Vec::new(), printed : true, size : (0, 0), redraw : true, open : true};
+        frontend.enter()?;
+
+        Ok(frontend)
+    }
+
+    fn enter(&mut self) -> io::Result<()>{
+        terminal::enable_raw_mode()?;
+        ACTIVE.store(true, Ordering::SeqCst);
+        RELEASES.store(self.releases, Ordering::SeqCst);
+
+        let mut out = io::stdout();
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+        if self.releases{
+            execute!(out, event::PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        self.redraw = true;
+
+        Ok(())
+    }
+
+    /// Reads every waiting terminal event
+    fn poll(&mut self) -> io::Result<()>{
+        while event::poll(Duration::ZERO)?{
+            let key = match event::read()?{
+                Event::Key(key) => key,
+                Event::Resize(..) => {
+                    self.redraw = true;
+                    continue;
+                },
+                _ => continue
+            };
+            let code = match key.code{
+                KeyCode::Char(letter) => KeyCode::Char(letter.to_ascii_lowercase()),
+                code => code
+            };
+
+            match (key.kind, code){
+                (KeyEventKind::Release, code) => {
+                    self.held.remove(&code);
+                },
+                (_, KeyCode::Esc) => self.open = false,
+                (_, KeyCode::Char('c')) if key.modifiers.contains(KeyModifiers::CONTROL) => self.open = false,
+                (KeyEventKind::Press, KeyCode::F(number)) => {
+                    self.hotkeys.extend(HOTKEYS.iter().filter(|(key, _)| *key == number).map(|(_, hotkey)| *hotkey));
+                },
+                (_, code) => {
+                    self.held.insert(code, Instant::now());
+                }
+            }
+        }
+
+        if !self.releases{
+            self.held.retain(|_, last| last.elapsed() < HOLD);
+        }
+
+        Ok(())
+    }
+
+    fn render(&mut self, display: &Display){
+        let colour = |pixel: u8| {
+            let rgb = display.palette[(pixel & 0x3) as usize];
+            style::Color::Rgb {r : (rgb >> 16) as u8, g : (rgb >> 8) as u8, b : rgb as u8}
+        };
+        let mut picture = Vec::new();
+
+        for y in (0..display.height).step_by(2){
+            // Colours are only sent when they change from the cell before
+            let mut last = None;
+            for x in 0..display.width{
+                let cell = (display.get(x, y), if y + 1 < display.height {display.get(x, y + 1)} else {0});
+                if last != Some(cell){
+                    let _ = queue!(picture, style::SetForegroundColor(colour(cell.0)), style::SetBackgroundColor(colour(cell.1)));
+                    last = Some(cell);
+                }
+                let _ = queue!(picture, style::Print('\u{2580}'));
+            }
+            let _ = queue!(picture, style::ResetColor, style::Print("\r\n"));
+        }
+
+        if picture != self.picture{
+            self.picture = picture;
+            self.printed = false;
+        }
+    }
+
+    fn show(&mut self) -> io::Result<()>{
+        let mut out = io::stdout().lock();
+        queue!(out, cursor::MoveTo(0, 0))?;
+        out.write_all(&self.picture)?;
+        queue!(out, terminal::Clear(terminal::ClearType::UntilNewLine), style::Print(&self.status))?;
+        out.flush()
+    }
+}
+
+impl Frontend for TerminalFrontend{
+    fn is_open(&self) -> bool{
+        self.open
+    }
+
+    fn draw(&mut self, processor: &mut Cpu){
+        if let Err(err) = self.poll(){
+            self.status = err.to_string();
+        }
+
+        let display = &processor.curr_buffer;
+        if (display.width, display.height) != self.size{
+            self.size = (display.width, display.height);
+            let _ = execute!(io::stdout(), terminal::Clear(terminal::ClearType::All));
+            self.redraw = true;
+        }
+        if processor.draw_flag || self.redraw{
+            self.render(&processor.curr_buffer);
+            processor.draw_flag = false;
+            self.redraw = false;
+            if let Err(err) = self.show(){
+                self.status = err.to_string();
+            }
+        }
+    }
+
+    /// True while Backspace is held
+    fn rewinding(&self) -> bool{
+        self.held.contains_key(&KeyCode::Backspace)
+    }
+
+    fn hotkeys(&mut self) -> Vec<Hotkey>{
+        std::mem::take(&mut self.hotkeys)
+    }
+
+    fn update_keys(&mut self, processor: &mut Cpu){
+        let mut keypad = [false; 16];
+        for (key, value) in KEYS{
+            keypad[value as usize] = self.held.contains_key(&KeyCode::Char(key));
+        }
+
+        processor.set_keypad(keypad);
+    }
+
+    /// Shown on the line under the screen
+    fn report(&mut self, message: &str){
+        self.status = message.to_string();
+        self.redraw = true;
+    }
+
+    /// Goes back to the normal screen and prints the picture there if it changed, so the prompt has it to hand
+    fn suspend(&mut self){
+        restore();
+        if !self.printed{
+            let mut out = io::stdout().lock();
+            let _ = out.write_all(&self.picture).and_then(|_| writeln!(out, "{}", self.status));
+            self.printed = true;
+        }
+        self.status.clear();
+    }
+
+    fn resume(&mut self){
+        if let Err(err) = self.enter(){
+            self.status = err.to_string();
+        }
+        self.held.clear();
+    }
+}
+
+impl Drop for TerminalFrontend{
+    fn drop(&mut self){
+        restore();
+    }
+}
+
+/// Puts the terminal back the way it was if a TerminalFrontend took it over
+pub fn restore(){
+    if !ACTIVE.swap(false, Ordering::SeqCst){
+        return;
+    }
+
+    let mut out = io::stdout();
+    if RELEASES.load(Ordering::SeqCst){
+        let _ = execute!(out, event::PopKeyboardEnhancementFlags);
+    }
+    let _ = execute!(out, style::ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+}
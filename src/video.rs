@@ -0,0 +1,182 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use crate::display::Display;
+use crate::screenshot::save_png;
+use crate::timing::FRAMES_PER_SECOND;
+
+/// Somewhere for the screen to go once per 60Hz frame
+pub trait VideoSink{
+    fn frame(&mut self, display: &Display) -> io::Result<()>;
+
+    /// Called once no more frames are coming
+    fn finish(&mut self) -> io::Result<()>{
+        Ok(())
+    }
+}
+
+/// Opens a GIF for paths ending in .gif, otherwise a directory of numbered PNGs
+pub fn create(path: &Path, scale: usize) -> io::Result<Box<dyn VideoSink>>{
+    if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("gif")){
+        Ok(Box::new(GifSink::new(BufWriter::new(File::create(path)?), scale)))
+    }
+    else{
+        Ok(Box::new(PngSequence::create(path, scale)?))
+    }
+}
+
+/// Writes `frame-000001.png` and on into a directory, one per frame, to be muxed at 60 fps
+pub struct PngSequence{
+    dir : PathBuf,
+    scale : usize,
+    frames : u64
+}
+
+impl PngSequence{
+    pub fn create(dir: &Path, scale: usize) -> io::Result<PngSequence>{
+        fs::create_dir_all(dir)?;
+        Ok(PngSequence {dir : dir.to_path_buf(), scale, frames : 0})
+    }
+}
+
+impl VideoSink for PngSequence{
+    fn frame(&mut self, display: &Display) -> io::Result<()>{
+        self.frames += 1;
+        save_png(display, self.scale, &self.dir.join(format!("frame-{:06}.png", self.frames)))
+    }
+}
+
+/// A frame waiting to be written until it is known how long it stays on screen
+struct Pending{
+    indices : Vec<u8>,
+    palette : [u32; 4],
+    /// Frame number it first appeared on
+    start : u64
+}
+
+/// Animated GIF at the size of the first frame. GIF delays are whole centiseconds and most viewers slow down
+/// anything shown for less than two, so repeated frames are merged and frames shorter than that are dropped,
+/// with every delay measured from the emulated frame count so the clip never drifts from 60 fps.
+pub struct GifSink<W: Write>{
+    writer : Option<W>,
+    encoder : Option<gif::Encoder<W>>,
+    scale : usize,
+    size : (usize, usize),
+    pending : Option<Pending>,
+    frames : u64
+}
+
+impl<W: Write> GifSink<W>{
+    pub fn new(writer: W, scale: usize) -> GifSink<W>{
+        GifSink {writer : Some(writer), encoder : None, scale : scale.max(1), size : (0, 0), pending : None, frames : 0}
+    }
+
+    /// The underlying writer, once finish has been called
+    pub fn into_inner(self) -> Option<W>{
+        self.writer
+    }
+
+    /// Hundredths of a second from the first frame to the start of `frame`
+    fn centiseconds(frame: u64) -> u64{
+        (frame * 100 + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND
+    }
+
+    /// Writes the pending frame if it stayed on screen until `end`, returns false if it was too short to show
+    fn flush(&mut self, end: u64, last: bool) -> io::Result<bool>{
+        let Some(pending) = self.pending.as_ref() else {return Ok(true)};
+        let delay = Self::centiseconds(end) - Self::centiseconds(pending.start);
+        if delay < 2 && !last{
+            return Ok(false);
+        }
+
+        let (width, height) = self.size;
+        if self.encoder.is_none(){
+            let writer = self.writer.take().expect("writer is only taken once");
+            let mut encoder = gif::Encoder::new(writer, width as u16, height as u16, &palette_bytes(&pending.palette)).map_err(io::Error::other)?;
+            encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+            self.encoder = Some(encoder);
+        }
+
+        let mut frame = gif::Frame::from_indexed_pixels(width as u16, height as u16, pending.indices.clone(), None);
+        frame.delay = delay.max(2) as u16;
+        frame.palette = Some(palette_bytes(&pending.palette));
+        self.encoder.as_mut().unwrap().write_frame(&frame).map_err(io::Error::other)?;
+
+        Ok(true)
+    }
+}
+
+impl<W: Write> VideoSink for GifSink<W>{
+    fn frame(&mut self, display: &Display) -> io::Result<()>{
+        if self.frames == 0{
+            self.size = (display.width * self.scale, display.height * self.scale);
+        }
+        let indices = palette_indices(display, self.size);
+        let frame = self.frames;
+        self.frames += 1;
+
+        if self.pending.as_ref().is_some_and(|pending| pending.indices == indices && pending.palette == display.palette){
+            return Ok(());
+        }
+        // A frame too short to show is replaced but keeps its start, so later frames stay on time
+        let start = match self.flush(frame, false)?{
+            true => frame,
+            false => self.pending.as_ref().unwrap().start
+        };
+        self.pending = Some(Pending {indices, palette : display.palette, start});
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()>{
+        self.flush(self.frames, true)?;
+        self.pending = None;
+        if let Some(encoder) = self.encoder.take(){
+            self.writer = Some(encoder.into_inner()?);
+        }
+
+        self.writer.as_mut().map_or(Ok(()), |writer| writer.flush())
+    }
+}
+
+fn palette_bytes(palette: &[u32; 4]) -> Vec<u8>{
+    palette.iter().flat_map(|&colour| [(colour >> 16) as u8, (colour >> 8) as u8, colour as u8]).collect()
+}
+
+/// The screen's palette indices stretched to `size`, which also covers switching between lores and hires mid-clip
+fn palette_indices(display: &Display, (width, height): (usize, usize)) -> Vec<u8>{
+    (0..height).flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| display.get(x * display.width / width, y * display.height / height) & 0x3).collect()
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{GifSink, VideoSink};
+    use crate::display::Display;
+
+    #[test]
+    fn gif_delays_follow_emulated_time(){
+        let mut display = Display::new();
+        let mut sink = GifSink::new(Vec::new(), 2);
+
+        // Half a second blank, then a pixel flickering every frame, so some frames are too short to show
+        for frame in 0..60{
+            display.pixels[0] = (frame == 30 || frame > 30 && frame % 2 == 1) as u8;
+            display.pixels[1] = (frame > 30) as u8;
+            sink.frame(&display).unwrap();
+        }
+        sink.finish().unwrap();
+        let gif = sink.into_inner().unwrap();
+
+        let mut decoder = gif::DecodeOptions::new().read_info(gif.as_slice()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (128, 64));
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap(){
+            delays.push(frame.delay);
+        }
+        assert_eq!(delays.iter().sum::<u16>(), 100);
+        assert_eq!(delays[0], 50);
+        assert!(delays.iter().all(|&delay| delay >= 2));
+    }
+}
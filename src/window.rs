@@ -0,0 +1,76 @@
+extern crate minifb;
+use minifb::{Window, WindowOptions, Key, KeyRepeat};
+use std::collections::hash_map::HashMap;
+use chip_8::Cpu;
+use crate::frontend::{Frontend, Hotkey};
+
+const HOTKEYS: [(Key, Hotkey); 3] = [(Key::F5, Hotkey::SaveState), (Key::F9, Hotkey::LoadState), (Key::F12, Hotkey::Screenshot)];
+
+/// A minifb window at ten times the CHIP-8 resolution
+pub struct WindowFrontend{
+    pub window : Window,
+    pub key_map : HashMap<Key, u8>
+}
+
+impl WindowFrontend{
+    pub fn new() -> WindowFrontend{
+        let mut window = Window::new("CHIP-8", 640, 320, WindowOptions::default()).unwrap();
+        window.update_with_buffer(&[0; 2048], 64, 32).unwrap();
+
+        let key_map = HashMap::from([
+            (Key::Key1,1_u8),
+            (Key::Key2,2),
+            (Key::Key3,3),
+            (Key::Key4,12),
+            (Key::Q,4),
+            (Key::W,5),
+            (Key::E,6),
+            (Key::R,13),
+            (Key::A,7),
+            (Key::S,8),
+            (Key::D,9),
+            (Key::F,14),
+            (Key::Z,10),
+            (Key::X,0),
+            (Key::C,11),
+            (Key::V,15),
+        ]);
+
+        WindowFrontend {window, key_map}
+    }
+}
+
+impl Frontend for WindowFrontend{
+    fn is_open(&self) -> bool{
+        self.window.is_open() && !self.window.is_key_down(Key::Escape)
+    }
+
+    /// Pushes the screen to the window if it changed, otherwise just pumps window events
+    fn draw(&mut self, processor: &mut Cpu){
+        if processor.draw_flag{
+            self.window.update_with_buffer(&processor.buffer(), processor.curr_buffer.width, processor.curr_buffer.height).unwrap();
+            processor.draw_flag = false;
+        }
+        else{
+            self.window.update();
+        }
+    }
+
+    /// True while Backspace is held to run the program backwards
+    fn rewinding(&self) -> bool{
+        self.window.is_key_down(Key::Backspace)
+    }
+
+    fn hotkeys(&mut self) -> Vec<Hotkey>{
+        HOTKEYS.iter().filter(|(key, _)| self.window.is_key_pressed(*key, KeyRepeat::No)).map(|(_, hotkey)| *hotkey).collect()
+    }
+
+    fn update_keys(&mut self, processor: &mut Cpu){
+        let mut keypad = [false; 16];
+        for (key, &val) in self.key_map.iter(){
+            keypad[val as usize] = self.window.is_key_down(*key);
+        }
+
+        processor.set_keypad(keypad);
+    }
+}
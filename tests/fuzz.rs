@@ -0,0 +1,104 @@
+use proptest::prelude::*;
+use chip_8::machine::MachineBuilder;
+use chip_8::{Cpu, Opcode, OpcodeTypes, Preset, Quirks};
+use chip_8::disasm::mnemonic;
+use chip_8::memory::MEMORY_SIZE;
+
+fn preset() -> impl Strategy<Value = Preset>{
+    prop_oneof![Just(Preset::CosmacVip), Just(Preset::Chip48), Just(Preset::Schip), Just(Preset::XoChip)]
+}
+
+/// A machine in any state a program could leave it in, including I and the pc at the top of memory and a full stack
+#[derive(Debug, Clone)]
+struct State{
+    regs : [u8; 16],
+    i : u16,
+    pc : u16,
+    stack : Vec<u16>,
+    timers : (u8, u8),
+    /// Bit n holds key n down
+    keys : u16,
+    preset : Preset,
+    hires : bool
+}
+
+impl State{
+    /// The pc is masked to the platform's memory, so it stays near the top when it was drawn there
+    fn build(&self) -> Cpu{
+        let size = Quirks::from(self.preset).platform.memory_size();
+        let pc = (self.pc as usize & (size - 1)).min(size - 2) as u16;
+        let machine = MachineBuilder::new().quirks(self.preset.into()).regs(&self.regs).i(self.i).pc(pc).stack(&self.stack).timers(self.timers.0, self.timers.1)
+            .keys(&(0..16).filter(|key| self.keys & (1 << key) != 0).collect::<Vec<u8>>());
+        if self.hires {machine.hires().build()} else {machine.build()}
+    }
+}
+
+/// An address anywhere, often near the top of memory where reads and writes run off the end
+fn addr(max: u16) -> impl Strategy<Value = u16>{
+    prop_oneof![0..=max, max - 0x20..=max]
+}
+
+/// Any word, or often a valid instruction with the kind picked first so rare kinds come up as much as common ones
+fn code() -> impl Strategy<Value = u16>{
+    let mut by_kind = vec![Vec::new(); OpcodeTypes::ALL.len()];
+    for code in 0..=u16::MAX{
+        if let Some(kind) = Opcode::find_kind(code).ok().and_then(|kind| OpcodeTypes::ALL.iter().position(|&other| other == kind)){
+            by_kind[kind].push(code);
+        }
+    }
+
+    prop_oneof![
+        1 => any::<u16>(),
+        3 => (0..by_kind.len(), any::<prop::sample::Index>()).prop_map(move |(kind, index)| *index.get(&by_kind[kind]))
+    ]
+}
+
+fn state() -> impl Strategy<Value = State>{
+    (any::<[u8; 16]>(), addr(0xFFFF), addr(0xFFFE), prop::collection::vec(any::<u16>(), 0..=16), any::<(u8, u8)>(), any::<u16>(), preset(), any::<bool>())
+        .prop_map(|(regs, i, pc, stack, timers, keys, preset, hires)| State {regs, i, pc, stack, timers, keys, preset, hires})
+}
+
+#[test]
+fn every_opcode_decodes_without_panicking(){
+    for code in 0..=u16::MAX{
+        let _ = Opcode::find_kind(code);
+        let _ = mnemonic(code, 0xFFFF);
+    }
+}
+
+proptest!{
+    // Single instructions are cheap, and need many cases to reach each rare kind with I or the pc near the top
+    #![proptest_config(ProptestConfig::with_cases(4096))]
+
+    #[test]
+    fn any_opcode_runs_on_any_machine(state in state(), code in code(), long in any::<u16>()){
+        let mut processor = state.build();
+        let (pc, size) = (processor.memory.pc as usize, processor.memory.addr_mem.len());
+        processor.memory.addr_mem[pc + 2..(pc + 4).min(size)].copy_from_slice(&long.to_be_bytes()[..(size - pc - 2).min(2)]);
+        processor.memory.addr_mem[pc..pc + 2].copy_from_slice(&code.to_be_bytes());
+
+        let _ = processor.step();
+        processor.tick_timers();
+    }
+}
+
+proptest!{
+    #[test]
+    fn any_rom_runs(rom in prop_oneof![prop::collection::vec(any::<u8>(), 0..MEMORY_SIZE),
+        prop::collection::vec(code(), 0..0x800).prop_map(|codes| codes.iter().flat_map(|code| code.to_be_bytes()).collect())], preset in preset(), keys in any::<u16>(), steps in 1..2000usize){
+        let Ok(mut processor) = Cpu::with_quirks(&rom, preset.into()) else {
+            return Ok(());
+        };
+        processor.set_seed(0);
+
+        for step in 0..steps{
+            if step % 11 == 0{
+                processor.tick_timers();
+                processor.set_keypad(std::array::from_fn(|key| keys.rotate_left(step as u32) & (1 << key) != 0));
+            }
+            if processor.step().is_err(){
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use chip_8::machine::MachineBuilder;
+use chip_8::{Cpu, Opcode, OpcodeTypes, Quirks};
+use chip_8::display::HIRES_WIDTH;
+
+/// One instruction run on a prepared machine and what the machine should look like afterwards
+struct Case{
+    name : &'static str,
+    machine : MachineBuilder,
+    code : u16,
+    check : Box<dyn Fn(&Cpu)>
+}
+
+fn case(name: &'static str, machine: MachineBuilder, code: u16, check: impl Fn(&Cpu) + 'static) -> Case{
+    Case {name, machine, code, check : Box::new(check)}
+}
+
+fn m() -> MachineBuilder{
+    MachineBuilder::new()
+}
+
+fn schip() -> MachineBuilder{
+    m().quirks(Quirks::schip())
+}
+
+fn xo() -> MachineBuilder{
+    m().quirks(Quirks::xo_chip())
+}
+
+fn pc(processor: &Cpu) -> u16{
+    processor.memory.pc
+}
+
+fn reg(processor: &Cpu, reg: usize) -> u8{
+    processor.memory.reg[reg]
+}
+
+fn mem(processor: &Cpu, addr: usize, len: usize) -> &[u8]{
+    &processor.memory.addr_mem[addr..addr + len]
+}
+
+fn cases() -> Vec<Case>{
+    vec![
+        case("CLS clears every pixel", m().pixels(&[0, 100, 2047], 1), 0x00E0, |p| {
+            assert!(p.curr_buffer.pixels.iter().all(|&pixel| pixel == 0));
+            assert_eq!(pc(p), 0x202);
+        }),
+        case("RET returns past the call", m().stack(&[0x300]), 0x00EE, |p| {
+            assert_eq!((pc(p), p.memory.sp, p.memory.stack[0]), (0x302, 0, None));
+        }),
+        case("JP sets the pc", m(), 0x1345, |p| assert_eq!(pc(p), 0x345)),
+        case("CALL pushes the pc", m(), 0x2345, |p| {
+            assert_eq!((pc(p), p.memory.sp, p.memory.stack[0]), (0x345, 1, Some(0x200)));
+        }),
+
+        case("SE Vx, byte skips when equal", m().reg(3, 0x42), 0x3342, |p| assert_eq!(pc(p), 0x204)),
+        case("SE Vx, byte runs on when different", m().reg(3, 0x42), 0x3343, |p| assert_eq!(pc(p), 0x202)),
+        case("SE Vx, byte skips only a word of F000 on the VIP", m().reg(3, 0x42).mem(0x202, &[0xF0, 0x00]), 0x3342, |p| assert_eq!(pc(p), 0x204)),
+        case("SE Vx, byte skips a long instruction whole", xo().reg(3, 0x42).mem(0x202, &[0xF0, 0x00]), 0x3342, |p| assert_eq!(pc(p), 0x206)),
+        case("SNE Vx, byte skips when different", m().reg(3, 0x42), 0x4343, |p| assert_eq!(pc(p), 0x204)),
+        case("SNE Vx, byte runs on when equal", m().reg(3, 0x42), 0x4342, |p| assert_eq!(pc(p), 0x202)),
+        case("SE Vx, Vy skips when equal", m().reg(1, 7).reg(2, 7), 0x5120, |p| assert_eq!(pc(p), 0x204)),
+        case("SE Vx, Vy runs on when different", m().reg(1, 7).reg(2, 8), 0x5120, |p| assert_eq!(pc(p), 0x202)),
+        case("SNE Vx, Vy skips a whole instruction when different", m().reg(1, 7).reg(2, 8), 0x9120, |p| assert_eq!(pc(p), 0x204)),
+        case("SNE Vx, Vy runs on when equal", m().reg(1, 7).reg(2, 7), 0x9120, |p| assert_eq!(pc(p), 0x202)),
+
+        case("LD Vx, byte", m(), 0x6A12, |p| assert_eq!(reg(p, 0xA), 0x12)),
+        case("ADD Vx, byte wraps and leaves VF alone", m().reg(0xA, 0xFF).reg(0xF, 5), 0x7A02, |p| {
+            assert_eq!((reg(p, 0xA), reg(p, 0xF)), (0x01, 5));
+        }),
+        case("LD Vx, Vy", m().reg(2, 0x34), 0x8120, |p| assert_eq!(reg(p, 1), 0x34)),
+        case("OR resets VF on the VIP", m().reg(1, 0x0F).reg(2, 0xF0).reg(0xF, 1), 0x8121, |p| {
+            assert_eq!((reg(p, 1), reg(p, 0xF)), (0xFF, 0));
+        }),
+        case("OR keeps VF without the quirk", schip().reg(1, 0x0F).reg(2, 0xF0).reg(0xF, 1), 0x8121, |p| {
+            assert_eq!((reg(p, 1), reg(p, 0xF)), (0xFF, 1));
+        }),
+        case("AND", m().reg(1, 0x3C).reg(2, 0x0F).reg(0xF, 1), 0x8122, |p| assert_eq!((reg(p, 1), reg(p, 0xF)), (0x0C, 0))),
+        case("XOR", m().reg(1, 0x3C).reg(2, 0x0F).reg(0xF, 1), 0x8123, |p| assert_eq!((reg(p, 1), reg(p, 0xF)), (0x33, 0))),
+
+        case("ADD Vx, Vy sets VF on carry", m().reg(1, 0xF0).reg(2, 0x20), 0x8124, |p| assert_eq!((reg(p, 1), reg(p, 0xF)), (0x10, 1))),
+        case("ADD Vx, Vy clears VF without carry", m().reg(1, 0x10).reg(2, 0x20).reg(0xF, 1), 0x8124, |p| {
+            assert_eq!((reg(p, 1), reg(p, 0xF)), (0x30, 0));
+        }),
+        case("ADD VF, Vy leaves the flag in VF", m().reg(0xF, 0x10).reg(1, 0xF0), 0x8F14, |p| assert_eq!(reg(p, 0xF), 1)),
+
+        case("SUB sets VF when nothing is borrowed", m().reg(1, 5).reg(2, 3), 0x8125, |p| assert_eq!((reg(p, 1), reg(p, 0xF)), (2, 1))),
+        case("SUB clears VF on borrow", m().reg(1, 3).reg(2, 5).reg(0xF, 1), 0x8125, |p| assert_eq!((reg(p, 1), reg(p, 0xF)), (0xFE, 0))),
+        case("SUB of equal values sets VF", m().reg(1, 9).reg(2, 9), 0x8125, |p| assert_eq!((reg(p, 1), reg(p, 0xF)), (0, 1))),
+        case("SUB VF, Vy leaves the flag in VF", m().reg(0xF, 5).reg(1, 3), 0x8F15, |p| assert_eq!(reg(p, 0xF), 1)),
+        case("SUBN sets VF when nothing is borrowed", m().reg(1, 3).reg(2, 5), 0x8127, |p| assert_eq!((reg(p, 1), reg(p, 0xF)), (2, 1))),
+        case("SUBN clears VF on borrow", m().reg(1, 5).reg(2, 3).reg(0xF, 1), 0x8127, |p| assert_eq!((reg(p, 1), reg(p, 0xF)), (0xFE, 0))),
+        case("SUBN VF, Vy leaves the flag in VF", m().reg(0xF, 3).reg(1, 5), 0x8F17, |p| assert_eq!(reg(p, 0xF), 1)),
+
+        case("SHR shifts Vy into Vx on the VIP", m().reg(1, 0xFF).reg(2, 0x81), 0x8126, |p| assert_eq!((reg(p, 1), reg(p, 0xF)), (0x40, 1))),
+        case("SHR shifts Vx in place with the quirk", schip().reg(1, 0x04).reg(2, 0x81), 0x8126, |p| {
+            assert_eq!((reg(p, 1), reg(p, 0xF)), (0x02, 0));
+        }),
+        case("SHR VF leaves the flag in VF", m().reg(0xF, 0x00).reg(1, 0x03), 0x8F16, |p| assert_eq!(reg(p, 0xF), 1)),
+        case("SHL sets VF to 1 from the top bit", m().reg(2, 0x81), 0x812E, |p| assert_eq!((reg(p, 1), reg(p, 0xF)), (0x02, 1))),
+        case("SHL clears VF", m().reg(2, 0x40).reg(0xF, 1), 0x812E, |p| assert_eq!((reg(p, 1), reg(p, 0xF)), (0x80, 0))),
+
+        case("LD I, addr", m(), 0xA123, |p| assert_eq!(p.memory.i, 0x123)),
+        case("JP V0, addr adds V0", m().reg(0, 4).reg(3, 8), 0xB310, |p| assert_eq!(pc(p), 0x314)),
+        case("JP V0, addr adds Vx with the quirk", schip().reg(0, 4).reg(3, 8), 0xB310, |p| assert_eq!(pc(p), 0x318)),
+        case("RND masks the random byte", m().reg(1, 0xFF), 0xC10F, |p| assert!(reg(p, 1) <= 0x0F)),
+
+        case("DRW draws and clears VF", m().reg(0xF, 1), 0xD005, |p| {
+            assert_eq!(&p.curr_buffer.pixels[..4], [1, 1, 1, 1]);
+            assert_eq!(reg(p, 0xF), 0);
+            assert!(p.draw_flag && p.vblank_wait);
+        }),
+        case("DRW sets VF on collision", m().pixels(&[1], 1), 0xD001, |p| {
+            assert_eq!(&p.curr_buffer.pixels[..4], [1, 0, 1, 1]);
+            assert_eq!(reg(p, 0xF), 1);
+        }),
+
+        case("SKP skips when the key is down", m().reg(1, 0xA).keys(&[0xA]), 0xE19E, |p| assert_eq!(pc(p), 0x204)),
+        case("SKP runs on when the key is up", m().reg(1, 0xA).keys(&[0xB]), 0xE19E, |p| assert_eq!(pc(p), 0x202)),
+        case("SKNP skips when the key is up", m().reg(1, 0xA), 0xE1A1, |p| assert_eq!(pc(p), 0x204)),
+        case("SKNP runs on when the key is down", m().reg(1, 0xA).keys(&[0xA]), 0xE1A1, |p| assert_eq!(pc(p), 0x202)),
+
+        case("LD Vx, DT", m().timers(0x30, 0), 0xF107, |p| assert_eq!(reg(p, 1), 0x30)),
+        case("LD Vx, K waits for a key", m(), 0xF10A, |p| assert_eq!(pc(p), 0x200)),
+        case("LD Vx, K takes a new key press", m().keys(&[7]), 0xF10A, |p| assert_eq!((reg(p, 1), pc(p)), (7, 0x202))),
+        case("LD DT, Vx", m().reg(1, 9), 0xF115, |p| assert_eq!(p.memory.delay, 9)),
+        case("LD ST, Vx", m().reg(1, 9), 0xF118, |p| assert_eq!(p.memory.sound, 9)),
+        case("ADD I, Vx", m().i(0x100).reg(1, 0x20).reg(0xF, 3), 0xF11E, |p| assert_eq!((p.memory.i, reg(p, 0xF)), (0x120, 3))),
+        case("LD F, Vx points at the digit", m().reg(1, 0xA), 0xF129, |p| {
+            assert_eq!(p.memory.i, 50);
+            assert_eq!(mem(p, 50, 5), [0xF0, 0x90, 0xF0, 0x90, 0x90]);
+        }),
+        case("LD B, Vx stores decimal digits", m().reg(1, 234).i(0x300), 0xF133, |p| {
+            assert_eq!(mem(p, 0x300, 3), [2, 3, 4]);
+            assert_eq!(p.memory.i, 0x300);
+        }),
+        case("LD [I], Vx stores V0 to Vx and moves I on", m().regs(&[1, 2, 3, 4]).i(0x300), 0xF255, |p| {
+            assert_eq!(mem(p, 0x300, 4), [1, 2, 3, 0]);
+            assert_eq!(p.memory.i, 0x303);
+        }),
+        case("LD [I], Vx leaves I with the quirk", schip().regs(&[1, 2, 3]).i(0x300), 0xF255, |p| {
+            assert_eq!(mem(p, 0x300, 3), [1, 2, 3]);
+            assert_eq!(p.memory.i, 0x300);
+        }),
+        case("LD Vx, [I] loads V0 to Vx and moves I on", m().mem(0x300, &[1, 2, 3, 4]).i(0x300), 0xF265, |p| {
+            assert_eq!(p.memory.reg[..4], [1, 2, 3, 0]);
+            assert_eq!(p.memory.i, 0x303);
+        }),
+
+        case("SCD scrolls down", schip().hires().pixels(&[0], 1), 0x00C2, |p| {
+            assert_eq!((p.curr_buffer.pixels[0], p.curr_buffer.pixels[2 * HIRES_WIDTH]), (0, 1));
+        }),
+        case("SCR scrolls right by 4", schip().hires().pixels(&[0], 1), 0x00FB, |p| assert_eq!(&p.curr_buffer.pixels[..5], [0, 0, 0, 0, 1])),
+        case("SCL scrolls left by 4", schip().hires().pixels(&[4], 1), 0x00FC, |p| assert_eq!(&p.curr_buffer.pixels[..5], [1, 0, 0, 0, 0])),
+        case("EXIT stops the machine", schip(), 0x00FD, |p| assert!(p.exited)),
+        case("LOW goes back to 64x32", schip().hires(), 0x00FE, |p| assert_eq!((p.curr_buffer.width, p.curr_buffer.hires), (64, false))),
+        case("HIGH switches to 128x64", schip(), 0x00FF, |p| assert_eq!((p.curr_buffer.width, p.curr_buffer.height), (128, 64))),
+        case("LD HF, Vx points at the big digit", schip().reg(1, 2), 0xF130, |p| assert_eq!(p.memory.i, 0x50 + 20)),
+        case("LD R, Vx saves V0 to Vx", schip().regs(&[1, 2, 3, 4]), 0xF275, |p| assert_eq!(p.memory.rpl[..4], [1, 2, 3, 0])),
+        case("LD Vx, R restores V0 to Vx", schip().rpl(&[1, 2, 3, 4]), 0xF285, |p| assert_eq!(p.memory.reg[..4], [1, 2, 3, 0])),
+
+        case("SCU scrolls up", xo().pixels(&[64], 1), 0x00D1, |p| assert_eq!((p.curr_buffer.pixels[0], p.curr_buffer.pixels[64]), (1, 0))),
+        case("LD [I], Vx, Vy stores a range without moving I", xo().regs(&[0, 1, 2, 3]).i(0x300), 0x5132, |p| {
+            assert_eq!(mem(p, 0x300, 4), [1, 2, 3, 0]);
+            assert_eq!(p.memory.i, 0x300);
+        }),
+        case("LD [I], Vx, Vy stores backwards when Vx is higher", xo().regs(&[0, 1, 2, 3]).i(0x300), 0x5312, |p| {
+            assert_eq!(mem(p, 0x300, 3), [3, 2, 1]);
+        }),
+        case("LD Vx, Vy, [I] loads a range", xo().mem(0x300, &[7, 8]).i(0x300), 0x5233, |p| {
+            assert_eq!(p.memory.reg[1..5], [0, 7, 8, 0]);
+            assert_eq!(p.memory.i, 0x300);
+        }),
+        case("LD I, long reads the next word", xo().mem(0x202, &[0x12, 0x34]), 0xF000, |p| assert_eq!((p.memory.i, pc(p)), (0x1234, 0x204))),
+        case("PLANE selects planes", xo(), 0xF201, |p| assert_eq!(p.curr_buffer.plane, 2)),
+        case("AUDIO loads the pattern", xo().mem(0x300, &[0xAA; 16]).i(0x300), 0xF002, |p| {
+            assert_eq!(p.memory.pattern, [0xAA; 16]);
+            assert!(p.memory.pattern_loaded);
+        }),
+        case("LD PITCH, Vx", xo().reg(1, 0x70), 0xF13A, |p| assert_eq!(p.memory.pitch, 0x70))
+    ]
+}
+
+/// Instructions that were once wrong, each checked the way the old bug would show
+fn regressions() -> Vec<Case>{
+    let all: Vec<u8> = (1..=16).collect();
+    vec![
+        // Only Vx was written, once per loop iteration
+        case("LD [I], VF stores every register", m().regs(&all).i(0x300), 0xFF55, move |p| assert_eq!(mem(p, 0x300, 16), (1..=16).collect::<Vec<u8>>())),
+        case("LD VF, [I] loads every register", m().mem(0x300, &all).i(0x300), 0xFF65, move |p| assert_eq!(p.memory.reg.to_vec(), (1..=16).collect::<Vec<u8>>())),
+        // VF held the shifted out bit in place, 0x80
+        case("SHL puts 1 in VF, not the top bit", m().reg(2, 0xFF), 0x812E, |p| assert_eq!((reg(p, 1), reg(p, 0xF)), (0xFE, 1)))
+    ]
+}
+
+fn failing(cases: Vec<Case>) -> Vec<String>{
+    cases.into_iter().filter_map(|case| {
+        let processor = match case.machine.run(case.code){
+            Ok(processor) => processor,
+            Err(err) => return Some(format!("{}: {}", case.name, err))
+        };
+        catch_unwind(AssertUnwindSafe(|| (case.check)(&processor))).err().map(|_| case.name.to_string())
+    }).collect()
+}
+
+#[test]
+fn opcodes_behave(){
+    let failures = failing(cases());
+    assert!(failures.is_empty(), "failing cases:\n{}", failures.join("\n"));
+}
+
+#[test]
+fn fixed_bugs_stay_fixed(){
+    let failures = failing(regressions());
+    assert!(failures.is_empty(), "failing cases:\n{}", failures.join("\n"));
+}
+
+#[test]
+fn every_opcode_has_a_case(){
+    let covered: Vec<OpcodeTypes> = cases().iter().map(|case| Opcode::find_kind(case.code).unwrap()).collect();
+    let missing: Vec<&OpcodeTypes> = OpcodeTypes::ALL.iter().filter(|kind| !covered.contains(kind)).collect();
+
+    assert!(missing.is_empty(), "no case for {:?}", missing);
+}
@@ -0,0 +1,86 @@
+use std::path::Path;
+use chip_8::asm::assemble;
+use chip_8::quirks::Quirks;
+use chip_8::snapshot::{assert_snapshot, run_rom, Until};
+
+/// Assembles `source`, runs it for a few frames and checks the screen against tests/snapshots/NAME.txt
+fn snapshot(name: &str, source: &str, quirks: Quirks){
+    let rom = assemble(source).unwrap();
+    let processor = run_rom(&rom, quirks, Until::Frame(10)).unwrap();
+
+    assert_snapshot(&processor.curr_buffer, &Path::new("tests/snapshots").join(format!("{}.txt", name)));
+}
+
+const SPRITES: &str = "
+    LD V0, 0x0A
+    LD F, V0
+    LD V1, 0x3D
+    LD V2, 0x1D
+    DRW V1, V2, 5
+    LD V1, 0x02
+    LD V2, 0x02
+    DRW V1, V2, 5
+    ADD V1, 0x02
+    DRW V1, V2, 5
+end:
+    JP end
+";
+
+#[test]
+fn drw_clips_at_edges(){
+    snapshot("drw_clip", SPRITES, Quirks::cosmac_vip());
+}
+
+#[test]
+fn drw_wraps_with_quirk(){
+    snapshot("drw_wrap", SPRITES, Quirks::xo_chip());
+}
+
+#[test]
+fn cls_clears_screen(){
+    snapshot("cls", "
+        LD V0, 0x08
+        LD F, V0
+        DRW V0, V0, 5
+        CLS
+        LD V1, 0x10
+        DRW V1, V1, 5
+    end:
+        JP end
+    ", Quirks::cosmac_vip());
+}
+
+#[test]
+fn schip_scrolls(){
+    snapshot("scroll", "
+        HIGH
+        LD V0, 0x0F
+        LD F, V0
+        LD V1, 0x10
+        DRW V1, V1, 5
+        SCD 3
+        SCR
+        LD V1, 0x30
+        DRW V1, V1, 5
+        SCL
+    end:
+        JP end
+    ", Quirks::schip());
+}
+
+#[test]
+fn xo_chip_planes(){
+    snapshot("planes", "
+        LD V0, 0x00
+        LD F, V0
+        PLANE 1
+        DRW V0, V0, 5
+        LD V1, 0x02
+        PLANE 2
+        DRW V1, V0, 5
+        PLANE 3
+        SCU 1
+    end:
+        JP end
+    ", Quirks::xo_chip());
+}
@@ -0,0 +1,14 @@
+use std::path::Path;
+use chip_8::testrom::{load_manifest, Outcome};
+
+#[test]
+fn test_roms_match_golden_images(){
+    let tests = load_manifest(Path::new("TestRoms/tests.txt")).unwrap();
+    assert!(!tests.is_empty());
+
+    let failures: Vec<String> = tests.iter().filter_map(|test| match test.check(false){
+        Outcome::Fail(reason) => Some(format!("{}: {}", test.name, reason)),
+        _ => None
+    }).collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}